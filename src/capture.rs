@@ -0,0 +1,379 @@
+use std::{fs::{self, OpenOptions},
+          io::{Read, Write},
+          path::{Path, PathBuf},
+          pin::Pin,
+          task::{Context as TaskContext, Poll},
+          time::Duration};
+
+use anyhow::{bail, Context, Error};
+use chrono::Utc;
+use tokio::{fs::File,
+            io::{AsyncRead, AsyncWriteExt, BufWriter, ReadBuf},
+            sync::mpsc::{self, error::TrySendError},
+            time::Instant};
+
+use crate::sml::FRAME_START;
+
+/// Start of a capture file of the raw bytes of a meter.
+///
+/// It is followed by one record per chunk read from the port: the time it
+/// was received as microseconds since the unix epoch (`u64`), its length
+/// (`u32`), both little endian, and the bytes.
+const MAGIC: &[u8] = b"SMLCAP\x00\x01";
+
+/// Time of the empty record which starts a recording appended to an existing
+/// capture, the pause between the recordings isn't replayed.
+const SESSION_MARKER: u64 = u64::MAX;
+
+/// Size of the header of a record, its time and length.
+const RECORD_HEADER_SIZE: usize = 12;
+
+/// Interval of the frames of a plain binary dump, which has no timestamps.
+const DUMP_FRAME_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Number of chunks queued for the writer of a capture file, further chunks
+/// are dropped.
+const RECORD_QUEUE_SIZE: usize = 64;
+
+/// Interval in which the written chunks are flushed to the capture file.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A chunk read at the time in microseconds since the unix epoch.
+type Record = (u64, Vec<u8>);
+
+/// Reader which appends every chunk read from `inner` to a capture file.
+///
+/// The chunks are written by a task of their own, so reading never waits for
+/// the disk.
+pub struct Recorder<R> {
+    inner:   R,
+    records: mpsc::Sender<Record>,
+}
+
+impl<R> Recorder<R> {
+    /// Appends to the capture file at `path`, which is created if it doesn't
+    /// exist, after a session marker.
+    pub fn new(inner: R, path: &Path) -> Result<Self, Error> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open capture file {}", path.display()))?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(MAGIC)?;
+        } else {
+            let mut magic = [0; MAGIC.len()];
+            if file.read_exact(&mut magic).is_err() || magic != MAGIC {
+                bail!("{} exists and is no capture file", path.display());
+            }
+            file.write_all(&SESSION_MARKER.to_le_bytes())?;
+            file.write_all(&0u32.to_le_bytes())?;
+        }
+
+        let (records, receiver) = mpsc::channel(RECORD_QUEUE_SIZE);
+        let file = BufWriter::new(File::from_std(file));
+        tokio::spawn(write_records(file, path.to_path_buf(), receiver));
+
+        Ok(Recorder { inner, records })
+    }
+
+    fn record(&self, bytes: Vec<u8>) {
+        let micros = Utc::now().timestamp_micros() as u64;
+        // A slow disk shouldn't stop the meter, the capture is only missing
+        // the chunk.
+        if let Err(TrySendError::Full(_)) = self.records.try_send((micros, bytes)) {
+            log::warn!("Capture file can't keep up, dropped a chunk");
+        }
+    }
+}
+
+/// Writes the records to `file` until the recorder is dropped.
+async fn write_records(
+    mut file: BufWriter<File>,
+    path: PathBuf,
+    mut records: mpsc::Receiver<Record>,
+) {
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+    loop {
+        let result = tokio::select! {
+            record = records.recv() => {
+                let Some((micros, bytes)) = record else {
+                    break;
+                };
+                write_record(&mut file, micros, &bytes).await
+            },
+            _ = flush.tick() => file.flush().await,
+        };
+        if let Err(e) = result {
+            log::warn!("Failed to write capture file {}: {e}", path.display());
+        }
+    }
+
+    if let Err(e) = file.flush().await {
+        log::warn!("Failed to write capture file {}: {e}", path.display());
+    }
+}
+
+async fn write_record(
+    file: &mut BufWriter<File>,
+    micros: u64,
+    bytes: &[u8],
+) -> std::io::Result<()> {
+    file.write_all(&micros.to_le_bytes()).await?;
+    file.write_all(&(bytes.len() as u32).to_le_bytes()).await?;
+    file.write_all(bytes).await
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Recorder<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = result {
+            let bytes = buf.filled()[filled..].to_vec();
+            if !bytes.is_empty() {
+                self.record(bytes);
+            }
+        }
+
+        result
+    }
+}
+
+/// Replays a capture file, or a plain binary dump of a port, as reader.
+///
+/// The chunks are delivered at their original pace divided by `speed`, `0`
+/// delivers them right away. The frames of a plain dump are delivered one
+/// per second. The reader ends with the file.
+pub fn replay(path: &Path, speed: f64) -> Result<impl AsyncRead, Error> {
+    let content =
+        fs::read(path).with_context(|| format!("Failed to read capture {}", path.display()))?;
+    let chunks = match content.strip_prefix(MAGIC) {
+        Some(records) => parse_records(records)?,
+        None => split_frames(&content),
+    };
+
+    let (mut writer, reader) = tokio::io::duplex(4096);
+    tokio::spawn(async move {
+        let start = Instant::now();
+        for (offset, bytes) in chunks {
+            if speed > 0.0 {
+                tokio::time::sleep_until(start + offset.div_f64(speed)).await;
+            }
+            if writer.write_all(&bytes).await.is_err() {
+                // The reader was dropped.
+                break;
+            }
+        }
+    });
+
+    Ok(reader)
+}
+
+/// The chunks of a capture file with their offset to the first one.
+///
+/// The chunks of a session appended to the capture continue right after the
+/// last chunk of the one before.
+fn parse_records(mut records: &[u8]) -> Result<Vec<(Duration, Vec<u8>)>, Error> {
+    let mut chunks = Vec::new();
+    let mut first = None;
+    let mut session_start = Duration::ZERO;
+
+    while !records.is_empty() {
+        if records.len() < RECORD_HEADER_SIZE {
+            bail!("Truncated record header in capture");
+        }
+        let (header, rest) = records.split_at(RECORD_HEADER_SIZE);
+        let micros = u64::from_le_bytes(header[..8].try_into()?);
+        let length = u32::from_le_bytes(header[8..].try_into()?) as usize;
+        if rest.len() < length {
+            bail!("Truncated record in capture");
+        }
+        let (bytes, rest) = rest.split_at(length);
+        records = rest;
+
+        if micros == SESSION_MARKER {
+            first = None;
+            session_start = chunks.last().map_or(Duration::ZERO, |(offset, _)| *offset);
+            continue;
+        }
+        let first = *first.get_or_insert(micros);
+        let offset = session_start + Duration::from_micros(micros.saturating_sub(first));
+        chunks.push((offset, bytes.to_vec()));
+    }
+
+    Ok(chunks)
+}
+
+/// Splits a plain dump at the start of every SML frame.
+fn split_frames(content: &[u8]) -> Vec<(Duration, Vec<u8>)> {
+    let mut starts: Vec<usize> = content
+        .windows(FRAME_START.len())
+        .enumerate()
+        .filter(|(_, window)| *window == FRAME_START)
+        .map(|(index, _)| index)
+        .collect();
+    if starts.first() != Some(&0) {
+        starts.insert(0, 0);
+    }
+    starts.push(content.len());
+
+    starts
+        .windows(2)
+        .enumerate()
+        .map(|(index, range)| {
+            (
+                DUMP_FRAME_INTERVAL * index as u32,
+                content[range[0]..range[1]].to_vec(),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempPath;
+
+    fn record(micros: u64, bytes: &[u8]) -> Vec<u8> {
+        let mut record = micros.to_le_bytes().to_vec();
+        record.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        record.extend_from_slice(bytes);
+        record
+    }
+
+    #[test]
+    fn parse_records_with_offsets() {
+        let mut records = record(5_000_000, &[1, 2, 3]);
+        records.extend(record(6_500_000, &[]));
+        records.extend(record(7_000_000, &[4]));
+
+        let chunks = parse_records(&records).unwrap();
+        assert_eq!(chunks, vec![
+            (Duration::ZERO, vec![1, 2, 3]),
+            (Duration::from_millis(1500), vec![]),
+            (Duration::from_secs(2), vec![4]),
+        ]);
+    }
+
+    #[test]
+    fn parse_records_of_clock_going_back() {
+        let mut records = record(5_000_000, &[1]);
+        records.extend(record(4_000_000, &[2]));
+
+        let chunks = parse_records(&records).unwrap();
+        assert_eq!(chunks[1], (Duration::ZERO, vec![2]));
+    }
+
+    #[test]
+    fn parse_records_of_sessions() {
+        let mut records = record(5_000_000, &[1]);
+        records.extend(record(6_000_000, &[2]));
+        records.extend(record(SESSION_MARKER, &[]));
+        records.extend(record(90_000_000, &[3]));
+        records.extend(record(90_500_000, &[4]));
+
+        let chunks = parse_records(&records).unwrap();
+        assert_eq!(chunks, vec![
+            (Duration::ZERO, vec![1]),
+            (Duration::from_secs(1), vec![2]),
+            (Duration::from_secs(1), vec![3]),
+            (Duration::from_millis(1500), vec![4]),
+        ]);
+    }
+
+    /// Records `bytes` as one chunk to the capture at `path`.
+    async fn record_chunk(path: &Path, bytes: &'static [u8]) {
+        let length = fs::metadata(path).map_or(MAGIC.len() as u64, |metadata| metadata.len());
+        let mut recorder = Recorder::new(bytes, path).unwrap();
+        tokio::io::copy(&mut recorder, &mut tokio::io::sink())
+            .await
+            .unwrap();
+        drop(recorder);
+
+        // The writer flushes the file once the recorder is dropped.
+        let expected = length + (RECORD_HEADER_SIZE + bytes.len()) as u64;
+        while fs::metadata(path).unwrap().len() < expected {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn recorder_appends_session() {
+        let path = TempPath::new("capture.bin");
+        record_chunk(path.path(), &[1, 2]).await;
+        record_chunk(path.path(), &[3]).await;
+
+        let content = fs::read(path.path()).unwrap();
+        let records = content.strip_prefix(MAGIC).unwrap();
+        let chunks = parse_records(records).unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0], (Duration::ZERO, vec![1, 2]));
+        assert_eq!(chunks[1].1, vec![3]);
+        assert!(chunks[1].0 < Duration::from_secs(1));
+        assert_eq!(
+            content.len(),
+            MAGIC.len() + 3 * RECORD_HEADER_SIZE + 3,
+            "two chunks and the session marker"
+        );
+    }
+
+    #[test]
+    fn recorder_rejects_other_files() {
+        let path = TempPath::new("dump.bin");
+        fs::write(path.path(), FRAME_START).unwrap();
+
+        let error = Recorder::new(tokio::io::empty(), path.path())
+            .err()
+            .unwrap();
+        assert!(error.to_string().ends_with("is no capture file"));
+        assert_eq!(fs::read(path.path()).unwrap(), FRAME_START);
+    }
+
+    #[test]
+    fn parse_records_truncated() {
+        let records = record(5_000_000, &[1, 2, 3]);
+
+        assert!(parse_records(&records[..RECORD_HEADER_SIZE - 1]).is_err());
+        assert!(parse_records(&records[..records.len() - 1]).is_err());
+        assert!(parse_records(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn split_frames_at_frame_starts() {
+        let mut content = vec![0xaa, 0xbb];
+        content.extend_from_slice(FRAME_START);
+        content.push(1);
+        content.extend_from_slice(FRAME_START);
+        content.push(2);
+
+        let chunks = split_frames(&content);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0], (Duration::ZERO, vec![0xaa, 0xbb]));
+        assert_eq!(chunks[1].0, DUMP_FRAME_INTERVAL);
+        assert_eq!(&chunks[1].1[..FRAME_START.len()], FRAME_START);
+        assert_eq!(chunks[1].1.len(), FRAME_START.len() + 1);
+        assert_eq!(chunks[2].0, DUMP_FRAME_INTERVAL * 2);
+        assert_eq!(chunks[2].1.last(), Some(&2));
+    }
+
+    #[test]
+    fn split_frames_starting_with_frame() {
+        let mut content = FRAME_START.to_vec();
+        content.push(1);
+
+        assert_eq!(split_frames(&content), vec![(Duration::ZERO, content)]);
+    }
+
+    #[test]
+    fn split_frames_without_frame() {
+        assert_eq!(split_frames(&[1, 2, 3]), vec![(Duration::ZERO, vec![
+            1, 2, 3
+        ])]);
+    }
+}
@@ -1,17 +1,31 @@
+use std::path::PathBuf;
+
 use anyhow::Error;
-use clap_derive::{Args};
-use crate::database::Database;
+use clap_derive::Args;
+
+use crate::{config::Config, database::Database};
 
 #[derive(Clone, Args)]
-pub struct DatabaseCommand { }
+pub struct DatabaseCommand {
+    /// Path of the TOML configuration file
+    #[arg(long, env = "POWER_METER_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Path of the SQLite database file, overrides the config file
+    #[arg(long, env = "POWER_METER_DATABASE")]
+    database: Option<PathBuf>,
+}
 
 impl DatabaseCommand {
     pub fn run(self) -> Result<(), Error> {
-        let db = Database::load()?;
+        let config = Config::load(self.config.as_deref())?;
+        let path = self.database.unwrap_or(config.database.path.clone());
+
+        let db = Database::open(&path, config.legacy_meter())?;
         let metrics = db.metrics()?;
-        
+
         println!("{metrics}");
-        
+
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -1,4 +1,5 @@
-pub mod root_command;
-// mod database;
+mod database;
 mod ports;
+pub mod root_command;
+mod sniff;
 mod start;
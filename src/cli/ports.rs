@@ -1,28 +1,268 @@
-use anyhow::Error;
-use clap_derive::{Args};
+use std::{fs, path::PathBuf, time::Duration};
+
+use anyhow::{Context, Error};
+use clap_derive::Args;
+use serde::Serialize;
+use sml_rs::{parser::complete::MessageBody, transport::Decoder};
+use tokio::{io::AsyncReadExt, task::JoinSet, time::Instant};
+use tokio_serial::{SerialPortInfo, SerialPortType};
+
+use crate::{serial::{self, LineSettings},
+            server_id::ServerId};
+
+/// Directory of the stable symlinks udev creates for serial ports.
+const BY_ID_DIR: &str = "/dev/serial/by-id";
 
 #[derive(Clone, Args)]
-pub struct ListPortsCommand { }
+pub struct ListPortsCommand {
+    /// Print the ports as JSON instead of a table
+    #[arg(long)]
+    json: bool,
+
+    /// Listen on every port and report whether it receives SML frames
+    #[arg(long)]
+    probe: bool,
+
+    /// Seconds to listen on every port when probing
+    #[arg(long, default_value = "5", value_name = "SECONDS")]
+    probe_duration: u64,
+
+    /// Additional port to list, e.g. a UART which isn't detected
+    #[arg(long = "port", value_name = "PATH")]
+    ports: Vec<String>,
+}
+
+/// A serial port and the USB device it belongs to.
+#[derive(Serialize)]
+struct Port {
+    path:          String,
+    /// Stable path of the port below `/dev/serial/by-id`.
+    by_id:         Option<String>,
+    #[serde(rename = "type")]
+    port_type:     &'static str,
+    vid:           Option<u16>,
+    pid:           Option<u16>,
+    manufacturer:  Option<String>,
+    product:       Option<String>,
+    serial_number: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    probe:         Option<Probe>,
+}
+
+/// What was received on a port while probing.
+#[derive(Default, Serialize)]
+struct Probe {
+    bytes:      usize,
+    /// Number of complete SML frames with a valid checksum.
+    sml_frames: usize,
+    server_id:  Option<ServerId>,
+    error:      Option<String>,
+}
 
 impl ListPortsCommand {
-    pub fn run(self) -> Result<(), Error> {
-        let ports = serialport::available_ports();
-        
-        match ports {
-            Ok(ports) => {
-                if ports.is_empty() {
-                    println!("No ports available.");
-                }
-                
-                for port in ports {
-                    println!("{:?}", port);
-                }
-            },
-            Err(e) => {
-                println!("Could not fetch available ports: {:?}", e);
+    pub async fn run(self) -> Result<(), Error> {
+        let mut ports: Vec<Port> = tokio_serial::available_ports()
+            .context("Failed to list serial ports")?
+            .into_iter()
+            .map(Port::new)
+            .collect();
+        for path in &self.ports {
+            if !ports.iter().any(|port| &port.path == path) {
+                ports.push(Port::new(SerialPortInfo {
+                    port_name: path.clone(),
+                    port_type: SerialPortType::Unknown,
+                }));
+            }
+        }
+
+        if self.probe {
+            let duration = Duration::from_secs(self.probe_duration);
+            let mut probes = JoinSet::new();
+            for (index, port) in ports.iter().enumerate() {
+                let path = port.path.clone();
+                probes.spawn(async move { (index, probe(&path, duration).await) });
+            }
+            while let Some(result) = probes.join_next().await {
+                let (index, probe) = result?;
+                ports[index].probe = Some(probe);
             }
         }
-        
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&ports)?);
+        } else if ports.is_empty() {
+            println!("No ports available.");
+        } else {
+            print_table(&ports, self.probe);
+        }
+
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+impl Port {
+    fn new(info: SerialPortInfo) -> Self {
+        let by_id = by_id_path(&info.port_name);
+        let mut port = Port {
+            path: info.port_name,
+            by_id,
+            port_type: "unknown",
+            vid: None,
+            pid: None,
+            manufacturer: None,
+            product: None,
+            serial_number: None,
+            probe: None,
+        };
+
+        match info.port_type {
+            SerialPortType::UsbPort(usb) => {
+                port.port_type = "usb";
+                port.vid = Some(usb.vid);
+                port.pid = Some(usb.pid);
+                port.manufacturer = usb.manufacturer;
+                port.product = usb.product;
+                port.serial_number = usb.serial_number;
+            },
+            SerialPortType::PciPort => port.port_type = "pci",
+            SerialPortType::BluetoothPort => port.port_type = "bluetooth",
+            SerialPortType::Unknown => {},
+        }
+
+        port
+    }
+}
+
+/// The symlink below `/dev/serial/by-id` which points to `path`, if any.
+fn by_id_path(path: &str) -> Option<String> {
+    let target = fs::canonicalize(path).ok()?;
+
+    fs::read_dir(BY_ID_DIR)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|link| fs::canonicalize(link).ok().as_ref() == Some(&target))
+        .map(|link: PathBuf| link.display().to_string())
+}
+
+/// Listens on the port for `duration` and counts the SML frames received.
+async fn probe(path: &str, duration: Duration) -> Probe {
+    let mut probe = Probe::default();
+
+    let mut port = match serial::open_port(path, &LineSettings::default()).await {
+        Ok(port) => port,
+        Err(e) => {
+            probe.error = Some(format!("{e:#}"));
+            return probe;
+        },
+    };
+
+    let deadline = Instant::now() + duration;
+    let mut decoder = Decoder::<Vec<u8>>::new();
+    let mut buf = [0; 512];
+    loop {
+        let n = match tokio::time::timeout_at(deadline, port.read(&mut buf)).await {
+            Ok(Ok(0)) | Err(_) => break,
+            Ok(Ok(n)) => n,
+            Ok(Err(e)) => {
+                probe.error = Some(format!("Failed to read from {path}: {e}"));
+                break;
+            },
+        };
+        probe.bytes += n;
+
+        for byte in &buf[..n] {
+            // Bytes outside of a frame are reported as errors, which is
+            // expected for the first frame.
+            let Ok(Some(frame)) = decoder.push_byte(*byte) else {
+                continue;
+            };
+            probe.sml_frames += 1;
+            if probe.server_id.is_none() {
+                probe.server_id = server_id(frame);
+            }
+        }
+    }
+
+    probe
+}
+
+/// The server id of the first open or list response of an SML frame.
+fn server_id(frame: &[u8]) -> Option<ServerId> {
+    let (file, _) = crate::sml::parse_messages(frame).ok()?;
+
+    file.messages.iter().find_map(|message| {
+        match &message.message_body {
+            MessageBody::OpenResponse(open) => Some(ServerId::new(open.server_id)),
+            MessageBody::GetListResponse(list) => Some(ServerId::new(list.server_id)),
+            MessageBody::CloseResponse(_) => None,
+        }
+    })
+}
+
+fn print_table(ports: &[Port], probed: bool) {
+    let mut rows = vec![vec![
+        "PORT".to_string(),
+        "BY-ID".to_string(),
+        "VID:PID".to_string(),
+        "MANUFACTURER".to_string(),
+        "PRODUCT".to_string(),
+        "SERIAL".to_string(),
+    ]];
+    if probed {
+        rows[0].push("SML".to_string());
+    }
+
+    for port in ports {
+        let mut row = vec![
+            port.path.clone(),
+            port.by_id.clone().unwrap_or_else(|| "-".to_string()),
+            match (port.vid, port.pid) {
+                (Some(vid), Some(pid)) => format!("{vid:04x}:{pid:04x}"),
+                _ => port.port_type.to_string(),
+            },
+            port.manufacturer.clone().unwrap_or_else(|| "-".to_string()),
+            port.product.clone().unwrap_or_else(|| "-".to_string()),
+            port.serial_number
+                .clone()
+                .unwrap_or_else(|| "-".to_string()),
+        ];
+        if let Some(probe) = &port.probe {
+            row.push(probe.summary());
+        }
+        rows.push(row);
+    }
+
+    let columns = rows[0].len();
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    for row in rows {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
+
+impl Probe {
+    fn summary(&self) -> String {
+        if let Some(error) = &self.error {
+            return format!("error: {error}");
+        }
+
+        match (self.sml_frames, &self.server_id) {
+            (0, _) => format!("no ({} bytes)", self.bytes),
+            (frames, Some(server_id)) => format!("yes ({frames} frames, {server_id})"),
+            (frames, None) => format!("yes ({frames} frames)"),
+        }
+    }
+}
@@ -1,9 +1,11 @@
-use clap::Command;
-use clap_derive::{Parser, Subcommand};
+use clap::ArgAction;
+use clap_derive::{Args, Parser, Subcommand};
 
-// use crate::cli::database::DatabaseCommand;
-use crate::cli::ports::ListPortsCommand;
-use crate::cli::start::StartCommand;
+use crate::{cli::{database::DatabaseCommand,
+                  ports::ListPortsCommand,
+                  sniff::SniffCommand,
+                  start::StartCommand},
+            logging::{self, LogFormat, LogSettings, LogTarget}};
 
 /// Rusty Power Meter - Copyright (c) 2024 Florian Gäbler
 #[derive(Parser)]
@@ -11,20 +13,78 @@ use crate::cli::start::StartCommand;
 pub struct RootCommand {
     #[command(subcommand)]
     pub command: Commands,
+
+    #[command(flatten)]
+    log: LogArgs,
 }
 
 #[derive(Clone, Subcommand)]
 pub enum Commands {
-    // Database(DatabaseCommand),
+    Database(DatabaseCommand),
     ListPorts(ListPortsCommand),
-    Start(StartCommand),
+    Sniff(SniffCommand),
+    Start(Box<StartCommand>),
+}
+
+// Log settings of every command, given before or after the command.
+#[derive(Clone, Args)]
+struct LogArgs {
+    /// Log more, `-v` for debug and `-vv` for trace messages
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Log less, `-q` for warnings, `-qq` for errors and `-qqq` for nothing
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    quiet: u8,
+
+    /// Levels per module in the syntax of `RUST_LOG`, e.g.
+    /// `power_meter::sml=debug,rumqttc=info` [default: $RUST_LOG]
+    #[arg(long, global = true, env = "POWER_METER_LOG_FILTER")]
+    log_filter: Option<String>,
+
+    /// Where the log is written to
+    #[arg(
+        long,
+        global = true,
+        env = "POWER_METER_LOG_TARGET",
+        value_enum,
+        default_value = "stderr"
+    )]
+    log_target: LogTarget,
+
+    /// Format of the log messages, journald always gets structured fields
+    #[arg(
+        long,
+        global = true,
+        env = "POWER_METER_LOG_FORMAT",
+        value_enum,
+        default_value = "text"
+    )]
+    log_format: LogFormat,
+}
+
+impl LogArgs {
+    fn settings(&self) -> LogSettings {
+        LogSettings {
+            verbosity: self.verbose as i8 - self.quiet as i8,
+            filter:    self
+                .log_filter
+                .clone()
+                .or_else(|| std::env::var("RUST_LOG").ok()),
+            target:    self.log_target,
+            format:    self.log_format,
+        }
+    }
 }
 
 impl RootCommand {
     pub async fn run(self) -> Result<(), anyhow::Error> {
+        logging::init(&self.log.settings())?;
+
         match self.command {
-            // Commands::Database(command) => command.run(),
-            Commands::ListPorts(command) => Ok(()), // command.run().await,
+            Commands::Database(command) => command.run(),
+            Commands::ListPorts(command) => command.run().await,
+            Commands::Sniff(command) => command.run().await,
             Commands::Start(command) => command.run().await,
         }
     }
@@ -0,0 +1,189 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Error};
+use clap_derive::Args;
+use sml_rs::transport::Decoder;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{capture,
+            cli::start::SerialArgs,
+            config::{Config, SerialLineConfig},
+            meter_reading::MeterReading,
+            obis_code::ObisCode,
+            register::RegisterTable,
+            serial,
+            sml::FRAME_START};
+
+/// Number of bytes per line of the hex dump.
+const HEX_LINE_LENGTH: usize = 32;
+
+/// Maximum number of raw bytes kept while no frame ends.
+const MAX_RAW_LENGTH: usize = 64 * 1024;
+
+#[derive(Clone, Args)]
+pub struct SniffCommand {
+    /// Serial port to read from
+    #[arg(long, conflicts_with = "file", required_unless_present = "file")]
+    port: Option<String>,
+
+    /// Capture file to read from, recorded with `start --record` or a plain
+    /// dump of a serial port
+    #[arg(long)]
+    file: Option<PathBuf>,
+
+    /// Only print the values with these OBIS codes, e.g. `1-0:1.8.0`
+    #[arg(long, value_parser = parse_obis_code)]
+    obis: Vec<ObisCode>,
+
+    /// Stop after this number of frames
+    #[arg(long)]
+    count: Option<usize>,
+
+    /// Don't print the hex dump of the transport frames
+    #[arg(long)]
+    no_hex: bool,
+
+    /// Don't print the decoded message tree
+    #[arg(long)]
+    no_tree: bool,
+
+    /// Path of the TOML configuration file, for the names of the registers
+    #[arg(long, env = "POWER_METER_CONFIG")]
+    config: Option<PathBuf>,
+
+    #[command(flatten)]
+    serial: SerialArgs,
+}
+
+impl SniffCommand {
+    pub async fn run(self) -> Result<(), Error> {
+        let config = Config::load(self.config.as_deref())?;
+        let registers = config.register_table()?;
+
+        let mut source: Box<dyn AsyncRead + Unpin + Send> = match (&self.port, &self.file) {
+            (Some(port), _) => {
+                let mut line_config = SerialLineConfig::default();
+                self.serial.apply(&mut line_config);
+                let settings = line_config.settings().context("Invalid serial settings")?;
+                Box::new(serial::open_port(port, &settings).await?)
+            },
+            (None, Some(file)) => Box::new(capture::replay(file, 0.0)?),
+            (None, None) => bail!("Either --port or --file is required"),
+        };
+
+        let mut decoder = Decoder::<Vec<u8>>::new();
+        let mut raw = Vec::new();
+        let mut frames = 0;
+        let mut buf = [0; 512];
+        while self.count.is_none_or(|count| frames < count) {
+            let n = source.read(&mut buf).await.context("Failed to read")?;
+            if n == 0 {
+                break;
+            }
+
+            for byte in &buf[..n] {
+                if raw.len() == MAX_RAW_LENGTH {
+                    // Keep the bytes from the last frame start on, or those
+                    // which may begin the next one.
+                    let start = raw
+                        .windows(FRAME_START.len())
+                        .rposition(|window| window == FRAME_START)
+                        .filter(|start| *start > 0)
+                        .unwrap_or(raw.len() - (FRAME_START.len() - 1));
+                    raw.drain(..start);
+                }
+                raw.push(*byte);
+                match decoder.push_byte(*byte) {
+                    Ok(None) => {},
+                    Ok(Some(frame)) => {
+                        frames += 1;
+                        // The raw bytes start with whatever preceded the frame.
+                        let start = raw
+                            .windows(FRAME_START.len())
+                            .rposition(|window| window == FRAME_START)
+                            .unwrap_or_default();
+                        self.print_frame(frames, &raw[start..], frame, &registers);
+                        raw.clear();
+
+                        if self.count == Some(frames) {
+                            break;
+                        }
+                    },
+                    Err(e) => println!("Transport error: {e:?}"),
+                }
+            }
+        }
+
+        println!("{frames} frames");
+        Ok(())
+    }
+
+    fn print_frame(&self, number: usize, raw: &[u8], frame: &[u8], registers: &RegisterTable) {
+        println!("=== Frame {number} ({} bytes) ===", raw.len());
+
+        if !self.no_hex {
+            for line in raw.chunks(HEX_LINE_LENGTH) {
+                let hex: Vec<String> = line.iter().map(|byte| format!("{byte:02x}")).collect();
+                println!("  {}", hex.join(" "));
+            }
+        }
+
+        let (file, diagnostics) = match crate::sml::parse_messages(frame) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                println!("Invalid SML file: {e}");
+                return;
+            },
+        };
+        for diagnostic in diagnostics {
+            println!("{diagnostic}");
+        }
+        if !self.no_tree {
+            println!("{file:#?}");
+        }
+
+        let reading = match MeterReading::parse(file, registers) {
+            Ok(reading) => reading,
+            Err(e) => {
+                println!("No reading: {e}");
+                return;
+            },
+        };
+        if let Some(server_id) = &reading.server_id {
+            let hex: String = server_id
+                .as_bytes()
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect();
+            println!("Server id: {server_id} ({hex})");
+        }
+        for (obis_code, value) in &reading.values {
+            if !self.obis.is_empty() && !self.obis.contains(obis_code) {
+                continue;
+            }
+
+            let mut line = format!(
+                "  {:<20} {:<18} {}",
+                obis_code.to_string(),
+                value.name.as_deref().unwrap_or("-"),
+                value.value
+            );
+            if let Some(unit) = &value.unit {
+                line += &format!(" {}", unit.as_str());
+            }
+            if let Some(decimal) = value.decimal() {
+                line += &format!(" (raw {}, scaler {})", decimal.raw, decimal.scaler);
+            }
+            if let Some(status) = value.status {
+                line += &format!(" status 0x{status:x}");
+            }
+            println!("{line}");
+        }
+    }
+}
+
+fn parse_obis_code(value: &str) -> Result<ObisCode, String> {
+    value
+        .parse()
+        .map_err(|e| format!("{e:?}, expected e.g. 1-0:1.8.0"))
+}
@@ -1,118 +1,695 @@
-use anyhow::{Context, Error};
+use std::{net::IpAddr, path::PathBuf, sync::Arc};
+
+use anyhow::{bail, Context, Error};
+use chrono::Utc;
 use clap_derive::Args;
-use tokio::io::AsyncRead;
-use tokio_serial::SerialStream;
+use tokio::{sync::{mpsc, watch},
+            task::JoinSet};
 use tokio_stream::StreamExt;
 
-use crate::meter_reading::MeterReading;
+use crate::{capture::Recorder,
+            config::{Config,
+                     DatabaseConfig,
+                     MeterConfig,
+                     MqttConfig,
+                     SerialConfig,
+                     SerialLineConfig,
+                     ServerConfig,
+                     DEFAULT_METER_NAME},
+            database::{Database, ReadonlyDatabase},
+            meter_reading::ReceivedReading,
+            mqtt::{MqttState, PayloadFormat, ProtocolVersion, Publisher, Status},
+            register::RegisterTable,
+            serial::{FlowControl, LineSettings, Parity},
+            server::{LatestReading, Meter as ServerMeter, Server},
+            source::{self, Source, SourceReader, SourceUrl}};
 
-const MQTT_CLIENT_NAME: &str = "HL-3-RZ-POWER-01";
-const MQTT_BROKER_ADDRESS: &str = "10.15.40.33";
-const MQTT_BROKER_PORT: u16 = 1883;
-const MQTT_TOPIC_PREFIX: &str = "power-meter/1-HLY03-0207-2343";
+/// Number of readings queued for the database writer before the reader waits
+/// for it.
+const DATABASE_QUEUE_SIZE: usize = 1024;
 
 #[derive(Clone, Args)]
 pub struct StartCommand {
-    #[arg(long)]
-    port: String,
+    /// Serial port of a single meter, e.g. `/dev/ttyUSB0` or a
+    /// `/dev/serial/by-id/` path, replaces the `[[meters]]` of the config file
+    #[arg(long, env = "POWER_METER_PORT")]
+    port: Option<String>,
+
+    /// Capture file, or plain binary dump, replayed as a single meter instead
+    /// of reading a port, replaces the `[[meters]]` of the config file
+    #[arg(long, env = "POWER_METER_REPLAY", conflicts_with = "port")]
+    replay: Option<PathBuf>,
+
+    /// Source of a single meter as URL, e.g. `tcp://10.0.0.5:8888` for a
+    /// network IR bridge, `serial:///dev/ttyUSB0?baud=9600`,
+    /// `file://capture.bin?speed=10` or `-` for stdin, replaces the
+    /// `[[meters]]` of the config file
+    #[arg(long, env = "POWER_METER_SOURCE", conflicts_with_all = ["port", "replay"])]
+    source: Option<SourceUrl>,
+
+    /// Speed of the replay, e.g. `10` for ten times the original pace or `0`
+    /// for as fast as possible
+    #[arg(long, env = "POWER_METER_REPLAY_SPEED")]
+    replay_speed: Option<f64>,
+
+    /// Capture file the raw bytes of the meter are appended to, requires a
+    /// single meter
+    #[arg(long, env = "POWER_METER_RECORD")]
+    record: Option<PathBuf>,
+
+    /// Path of the TOML configuration file
+    /// [default: <config dir>/power-meter/config.toml if it exists]
+    #[arg(long, env = "POWER_METER_CONFIG")]
+    config: Option<PathBuf>,
+
+    #[command(flatten)]
+    mqtt: MqttArgs,
+
+    #[command(flatten)]
+    server: ServerArgs,
+
+    #[command(flatten)]
+    database: DatabaseArgs,
 
-    #[arg(long, default_value = "false")]
-    verbose: bool,
+    #[command(flatten)]
+    serial: SerialArgs,
 }
 
-impl StartCommand {
-    pub async fn run(self) -> Result<(), Error> {
-        let uart = uart_ir_sensor_data_stream(self.port);
-        let mut stream = crate::meter_reading::sml_message_stream(uart);
-
-        let mut mqttoptions =
-            rumqttc::MqttOptions::new(MQTT_CLIENT_NAME, MQTT_BROKER_ADDRESS, MQTT_BROKER_PORT);
-        mqttoptions.set_keep_alive(std::time::Duration::from_secs(10));
-        // Last Will: broker marks us offline if the connection drops, so evcc
-        // sees a stale meter instead of a silently frozen last value.
-        mqttoptions.set_last_will(rumqttc::LastWill::new(
-            format!("{MQTT_TOPIC_PREFIX}/status"),
-            "offline",
-            rumqttc::QoS::AtLeastOnce,
-            true,
-        ));
-
-        let (client, mut eventloop) = rumqttc::AsyncClient::new(mqttoptions, 10);
-
-        tokio::spawn(async move {
-            loop {
-                let _ = eventloop.poll().await;
-            }
-        });
+// MQTT settings which override the `[mqtt]` section of the config file.
+//
+// Precedence is: command line flag > environment variable > config file >
+// built-in default.
+#[derive(Clone, Args)]
+struct MqttArgs {
+    /// Host name or address of the MQTT broker
+    #[arg(long, env = "POWER_METER_MQTT_HOST")]
+    mqtt_host: Option<String>,
+
+    /// TCP port of the MQTT broker
+    #[arg(long, env = "POWER_METER_MQTT_PORT")]
+    mqtt_port: Option<u16>,
+
+    /// Client id used to connect to the broker
+    #[arg(long, env = "POWER_METER_MQTT_CLIENT_ID")]
+    mqtt_client_id: Option<String>,
+
+    /// Version of the MQTT protocol
+    #[arg(long, env = "POWER_METER_MQTT_PROTOCOL_VERSION", value_enum)]
+    mqtt_protocol_version: Option<ProtocolVersion>,
+
+    /// Keep alive interval in seconds
+    #[arg(long, env = "POWER_METER_MQTT_KEEP_ALIVE")]
+    mqtt_keep_alive: Option<u64>,
+
+    #[arg(long, env = "POWER_METER_MQTT_USERNAME")]
+    mqtt_username: Option<String>,
 
-        // Announce availability (retained) once connected.
-        let _ = client
-            .publish(
-                format!("{MQTT_TOPIC_PREFIX}/status"),
-                rumqttc::QoS::AtLeastOnce,
-                true,
-                "online",
-            )
-            .await;
+    #[arg(long, env = "POWER_METER_MQTT_PASSWORD", hide_env_values = true)]
+    mqtt_password: Option<String>,
 
-        while let Some(event) = stream.next().await {
-            let _ = publish_data(&event, &client).await;
+    /// File the password is read from, e.g. `/run/secrets/mqtt-password`
+    #[arg(
+        long,
+        env = "POWER_METER_MQTT_PASSWORD_FILE",
+        conflicts_with = "mqtt_password"
+    )]
+    mqtt_password_file: Option<PathBuf>,
+
+    /// Prefix of the published topics, e.g. `power-meter/1-HLY03-0207-2343`
+    #[arg(long, env = "POWER_METER_MQTT_TOPIC_PREFIX")]
+    mqtt_topic_prefix: Option<String>,
+
+    /// Quality of service of the published readings (0, 1 or 2)
+    #[arg(long, env = "POWER_METER_MQTT_QOS")]
+    mqtt_qos: Option<u8>,
+
+    /// Whether the published readings are retained
+    #[arg(long, env = "POWER_METER_MQTT_RETAIN", value_name = "BOOL")]
+    mqtt_retain: Option<bool>,
+
+    /// Layout of the published readings
+    #[arg(long, env = "POWER_METER_MQTT_PAYLOAD", value_enum)]
+    mqtt_payload: Option<PayloadFormat>,
+
+    /// Whether the entries which aren't in the register table are published
+    /// under `<prefix>/obis/<code>`
+    #[arg(long, env = "POWER_METER_MQTT_PUBLISH_UNMAPPED", value_name = "BOOL")]
+    mqtt_publish_unmapped: Option<bool>,
+
+    /// Whether the topics of the readings are replaced by aliases (MQTT 5)
+    #[arg(long, env = "POWER_METER_MQTT_TOPIC_ALIASES", value_name = "BOOL")]
+    mqtt_topic_aliases: Option<bool>,
+
+    /// Whether the registers are announced to Home Assistant
+    #[arg(long, env = "POWER_METER_MQTT_DISCOVERY", value_name = "BOOL")]
+    mqtt_discovery: Option<bool>,
+
+    /// Whether the broker is connected by TLS
+    #[arg(long, env = "POWER_METER_MQTT_TLS", value_name = "BOOL")]
+    mqtt_tls: Option<bool>,
+
+    /// PEM bundle of the CAs of the broker [default: the ones of the system]
+    #[arg(long, env = "POWER_METER_MQTT_CA_FILE")]
+    mqtt_ca_file: Option<PathBuf>,
+
+    /// PEM certificate of the client, for brokers which authenticate clients
+    /// by certificate
+    #[arg(long, env = "POWER_METER_MQTT_CERT_FILE", requires = "mqtt_key_file")]
+    mqtt_cert_file: Option<PathBuf>,
+
+    /// PEM private key of the client certificate
+    #[arg(long, env = "POWER_METER_MQTT_KEY_FILE", requires = "mqtt_cert_file")]
+    mqtt_key_file: Option<PathBuf>,
+
+    /// Protocols offered by ALPN, e.g. `mqtt`
+    #[arg(long, env = "POWER_METER_MQTT_ALPN", value_delimiter = ',')]
+    mqtt_alpn: Option<Vec<String>>,
+
+    /// Name the certificate of the broker is verified against [default: the
+    /// host]
+    #[arg(long, env = "POWER_METER_MQTT_TLS_VERIFY_NAME")]
+    mqtt_tls_verify_name: Option<String>,
+}
+
+impl MqttArgs {
+    fn apply(self, config: &mut MqttConfig) {
+        if let Some(host) = self.mqtt_host {
+            config.host = host;
+        }
+        if let Some(port) = self.mqtt_port {
+            config.port = port;
+        }
+        if let Some(client_id) = self.mqtt_client_id {
+            config.client_id = client_id;
         }
+        if let Some(protocol_version) = self.mqtt_protocol_version {
+            config.protocol_version = protocol_version;
+        }
+        if let Some(keep_alive) = self.mqtt_keep_alive {
+            config.keep_alive = keep_alive;
+        }
+        if let Some(username) = self.mqtt_username {
+            config.username = Some(username);
+        }
+        // A password given here replaces a password file and vice versa.
+        if let Some(password) = self.mqtt_password {
+            config.password = Some(password);
+            config.password_file = None;
+        }
+        if let Some(password_file) = self.mqtt_password_file {
+            config.password_file = Some(password_file);
+            config.password = None;
+        }
+        if let Some(topic_prefix) = self.mqtt_topic_prefix {
+            config.topic_prefix = topic_prefix;
+        }
+        if let Some(qos) = self.mqtt_qos {
+            config.qos = qos;
+        }
+        if let Some(retain) = self.mqtt_retain {
+            config.retain = retain;
+        }
+        if let Some(payload) = self.mqtt_payload {
+            config.payload = payload;
+        }
+        if let Some(publish_unmapped) = self.mqtt_publish_unmapped {
+            config.publish_unmapped = publish_unmapped;
+        }
+        if let Some(topic_aliases) = self.mqtt_topic_aliases {
+            config.topic_aliases = topic_aliases;
+        }
+        if let Some(discovery) = self.mqtt_discovery {
+            config.discovery.enabled = discovery;
+        }
+        if let Some(tls) = self.mqtt_tls {
+            config.tls.enabled = tls;
+        }
+        if let Some(ca_file) = self.mqtt_ca_file {
+            config.tls.ca_file = Some(ca_file);
+        }
+        if let Some(cert_file) = self.mqtt_cert_file {
+            config.tls.cert_file = Some(cert_file);
+        }
+        if let Some(key_file) = self.mqtt_key_file {
+            config.tls.key_file = Some(key_file);
+        }
+        if let Some(alpn) = self.mqtt_alpn {
+            config.tls.alpn = alpn;
+        }
+        if let Some(verify_name) = self.mqtt_tls_verify_name {
+            config.tls.verify_name = Some(verify_name);
+        }
+    }
+}
+
+// HTTP server settings which override the `[server]` section of the config
+// file, with the same precedence as the MQTT settings.
+#[derive(Clone, Args)]
+struct ServerArgs {
+    /// Address the HTTP server listens on
+    #[arg(long, env = "POWER_METER_HTTP_BIND")]
+    http_bind: Option<IpAddr>,
 
-        Ok(())
+    /// TCP port of the HTTP server
+    #[arg(long, env = "POWER_METER_HTTP_PORT")]
+    http_port: Option<u16>,
+
+    /// Age in seconds after which the latest reading is served as stale
+    #[arg(long, env = "POWER_METER_HTTP_STALE_AFTER")]
+    http_stale_after: Option<u64>,
+}
+
+impl ServerArgs {
+    fn apply(self, config: &mut ServerConfig) {
+        if let Some(bind) = self.http_bind {
+            config.bind = bind;
+        }
+        if let Some(port) = self.http_port {
+            config.port = port;
+        }
+        if let Some(stale_after) = self.http_stale_after {
+            config.stale_after = stale_after;
+        }
     }
 }
 
-pub(crate) fn uart_ir_sensor_data_stream(port: String) -> impl AsyncRead {
-    let serial = tokio_serial::new(port, 9600);
-    SerialStream::open(&serial).unwrap()
+// Database settings which override the `[database]` section of the config
+// file, with the same precedence as the MQTT settings.
+#[derive(Clone, Args)]
+struct DatabaseArgs {
+    /// Whether every reading is stored in the database
+    #[arg(long, env = "POWER_METER_DATABASE_ENABLED", value_name = "BOOL")]
+    database_enabled: Option<bool>,
+
+    /// Path of the SQLite database file
+    /// [default: <data dir>/power-meter/readings.db]
+    #[arg(long, env = "POWER_METER_DATABASE")]
+    database: Option<PathBuf>,
+
+    /// Whether every numeric register is stored in the `Registers` table too
+    #[arg(
+        long,
+        env = "POWER_METER_DATABASE_STORE_REGISTERS",
+        value_name = "BOOL"
+    )]
+    database_store_registers: Option<bool>,
 }
 
-/// Publish every reading as **one raw numeric value per subtopic**, retained.
-///
-/// This is the layout evcc's `mqtt` plugin consumes directly (one topic = one
-/// value, no JSON/jq), e.g. the grid meter reads `<prefix>/power`:
-///   - `<prefix>/power`         momentary net power in W (+ import / − export, OBIS 16.7.0)
-///   - `<prefix>/energy_import` total drawn from grid in Wh (OBIS 1.8.0)
-///   - `<prefix>/energy_export` total fed into grid in Wh   (OBIS 2.8.0)
-///   - `<prefix>/l1` `/l2` `/l3` per-phase power in W
-///
-/// Retained so a reconnecting subscriber (evcc, Grafana) gets the last value
-/// immediately instead of waiting for the next SML telegram.
-pub async fn publish_data(
-    reading: &MeterReading,
-    mqtt_client: &rumqttc::AsyncClient,
-) -> Result<(), Error> {
-    async fn publish_field(
-        client: &rumqttc::AsyncClient,
-        topic: String,
-        value: f64,
-    ) -> Result<(), Error> {
-        client
-            .publish(topic, rumqttc::QoS::AtLeastOnce, true, format!("{value}"))
-            .await
-            .context("Failed to publish meter value")?;
-        Ok(())
+impl DatabaseArgs {
+    fn apply(self, config: &mut DatabaseConfig) {
+        if let Some(enabled) = self.database_enabled {
+            config.enabled = enabled;
+        }
+        if let Some(path) = self.database {
+            config.path = path;
+        }
+        if let Some(store_registers) = self.database_store_registers {
+            config.store_registers = store_registers;
+        }
     }
+}
+
+// Serial line settings which override the `serial` settings of every meter
+// in the config file, with the same precedence as the MQTT settings.
+#[derive(Clone, Args)]
+pub(crate) struct SerialArgs {
+    /// Baud rate of the serial port
+    #[arg(long, env = "POWER_METER_SERIAL_BAUD_RATE")]
+    serial_baud_rate: Option<u32>,
+
+    /// Number of data bits (5 to 8)
+    #[arg(long, env = "POWER_METER_SERIAL_DATA_BITS")]
+    serial_data_bits: Option<u8>,
+
+    #[arg(long, env = "POWER_METER_SERIAL_PARITY", value_enum)]
+    serial_parity: Option<Parity>,
+
+    /// Number of stop bits (1 or 2)
+    #[arg(long, env = "POWER_METER_SERIAL_STOP_BITS")]
+    serial_stop_bits: Option<u8>,
 
-    if let Some(value) = reading.current_net_power {
-        publish_field(mqtt_client, format!("{MQTT_TOPIC_PREFIX}/power"), value).await?;
+    #[arg(long, env = "POWER_METER_SERIAL_FLOW_CONTROL", value_enum)]
+    serial_flow_control: Option<FlowControl>,
+
+    /// Seconds without a reading after which the port is reopened, 0 waits
+    /// forever
+    #[arg(long, env = "POWER_METER_SERIAL_READ_TIMEOUT")]
+    serial_read_timeout: Option<u64>,
+
+    /// Bytes sent after opening the port as hex string, e.g. `2f3f210d0a`
+    #[arg(long, env = "POWER_METER_SERIAL_WAKE_UP")]
+    serial_wake_up: Option<String>,
+}
+
+impl SerialArgs {
+    pub(crate) fn apply(&self, config: &mut SerialLineConfig) {
+        if let Some(baud_rate) = self.serial_baud_rate {
+            config.baud_rate = Some(baud_rate);
+        }
+        if let Some(data_bits) = self.serial_data_bits {
+            config.data_bits = Some(data_bits);
+        }
+        if let Some(parity) = self.serial_parity {
+            config.parity = Some(parity);
+        }
+        if let Some(stop_bits) = self.serial_stop_bits {
+            config.stop_bits = Some(stop_bits);
+        }
+        if let Some(flow_control) = self.serial_flow_control {
+            config.flow_control = Some(flow_control);
+        }
+        if let Some(read_timeout) = self.serial_read_timeout {
+            config.read_timeout = Some(read_timeout);
+        }
+        if let Some(wake_up) = &self.serial_wake_up {
+            config.wake_up = Some(wake_up.clone());
+        }
     }
-    if let Some(value) = reading.total_energy_inbound {
-        publish_field(mqtt_client, format!("{MQTT_TOPIC_PREFIX}/energy_import"), value).await?;
+}
+
+impl StartCommand {
+    pub async fn run(self) -> Result<(), Error> {
+        log::info!(
+            "Starting Power-Meter (power-meter) v{}",
+            env!("CARGO_PKG_VERSION")
+        );
+
+        let mut config = Config::load(self.config.as_deref())?;
+        self.mqtt.apply(&mut config.mqtt);
+        self.server.apply(&mut config.server);
+        self.database.apply(&mut config.database);
+        if self.port.is_some() || self.replay.is_some() || self.source.is_some() {
+            config.meters = vec![MeterConfig {
+                name:         DEFAULT_METER_NAME.to_string(),
+                port:         self.port,
+                usb:          None,
+                source:       self.source,
+                replay:       self.replay,
+                replay_speed: 1.0,
+                record:       None,
+                serial:       SerialLineConfig::default(),
+                topic_prefix: None,
+            }];
+        }
+        if self.record.is_some() && config.meters.len() > 1 {
+            bail!("--record requires a single meter");
+        }
+        for meter in &mut config.meters {
+            meter.apply_source_settings();
+            self.serial.apply(&mut meter.serial);
+            if let Some(replay_speed) = self.replay_speed {
+                meter.replay_speed = replay_speed;
+            }
+            if let Some(record) = &self.record {
+                meter.record = Some(record.clone());
+            }
+        }
+        config.validate().context("Invalid configuration")?;
+        let registers = Arc::new(config.register_table()?);
+
+        let (database_tx, database_writer) = if config.database.enabled {
+            let mut database = Database::open(&config.database.path, config.legacy_meter())?;
+            database.store_registers(config.database.store_registers);
+            log::info!("Storing readings in {}", config.database.path.display());
+
+            let (database_tx, database_rx) = mpsc::channel(DATABASE_QUEUE_SIZE);
+            let database_writer = tokio::spawn(database.write_readings(
+                database_rx,
+                config.database.batch_size,
+                config.database.flush_interval(),
+            ));
+            (Some(database_tx), Some(database_writer))
+        } else {
+            (None, None)
+        };
+
+        // Opened after the writer, which creates and migrates the database.
+        let readonly_database = if config.database.enabled {
+            Some(ReadonlyDatabase::open(
+                &config.database.path,
+                config.server.query_timeout(),
+                config.server.query_max_rows,
+            )?)
+        } else {
+            None
+        };
+
+        // Every meter is read by a task of its own, so a failing port
+        // doesn't affect the other meters.
+        let mut meters = JoinSet::new();
+        let mut server_meters = Vec::new();
+        for meter in &config.meters {
+            let (latest_reading_tx, latest_reading_rx) = watch::channel(None);
+            let mqtt_state = Arc::new(MqttState::default());
+            server_meters.push(ServerMeter {
+                name:           meter.name.clone(),
+                latest_reading: LatestReading::new(latest_reading_rx, config.server.stale_after()),
+                mqtt:           mqtt_state.clone(),
+            });
+
+            let name = meter.name.clone();
+            let meter = run_meter(
+                meter.clone(),
+                config.meter_mqtt_config(meter),
+                config.serial.clone(),
+                mqtt_state,
+                registers.clone(),
+                database_tx.clone(),
+                latest_reading_tx,
+            );
+            meters.spawn(async move { (name, meter.await) });
+        }
+        // Only the meters hold a sender, so the writer stops with them.
+        drop(database_tx);
+
+        let server = Server::create(config.server.address(), server_meters, readonly_database);
+
+        let meters_stopped = async {
+            while let Some(result) = meters.join_next().await {
+                match result {
+                    Ok((name, Ok(()))) => log::info!("Meter {name} stopped"),
+                    Ok((name, Err(e))) => log::error!("Meter {name} failed: {e:#}"),
+                    Err(e) => log::error!("Meter task failed: {e}"),
+                }
+            }
+        };
+
+        let result = tokio::select! {
+            result = server.serve() => result.context("HTTP server failed"),
+            () = meters_stopped => Ok(()),
+        };
+        meters.shutdown().await;
+
+        // The meters owned the senders, so the writer flushes the pending
+        // readings and stops.
+        if let Some(database_writer) = database_writer {
+            database_writer.await?;
+        }
+
+        result
     }
-    if let Some(value) = reading.total_energy_outbound {
-        publish_field(mqtt_client, format!("{MQTT_TOPIC_PREFIX}/energy_export"), value).await?;
+}
+
+/// Reads `meter`, reopening its source whenever it fails or closes.
+///
+/// Every reading goes to the MQTT broker first, is queued for the database
+/// and then handed over to the HTTP server as the latest reading. While the
+/// source is down the meter is marked `offline` on `<prefix>/status`. A meter
+/// reading stdin or a replay stops when it ends.
+async fn run_meter(
+    meter: MeterConfig,
+    mut mqtt_config: MqttConfig,
+    serial_config: SerialConfig,
+    mqtt_state: Arc<MqttState>,
+    registers: Arc<RegisterTable>,
+    database_tx: Option<mpsc::Sender<ReceivedReading>>,
+    latest_reading_tx: watch::Sender<Option<ReceivedReading>>,
+) -> Result<(), Error> {
+    // A topic prefix with the server id is only known once the meter sent its
+    // first reading, until then the meter can't be marked offline.
+    let mut publisher = None;
+    if !mqtt_config.needs_server_id() {
+        publisher = Some(connect_mqtt(&meter, &mqtt_config, &mqtt_state, &registers)?);
     }
-    if let Some(value) = reading.line_one {
-        publish_field(mqtt_client, format!("{MQTT_TOPIC_PREFIX}/l1"), value).await?;
+    let source = meter.source();
+    let settings = meter.serial.settings()?;
+    let mut delay = serial_config.reconnect_delay();
+
+    loop {
+        match open_meter(&meter, &source, &settings).await {
+            Ok((port, uart)) => {
+                log::info!("Meter {}: connected to {port}", meter.name);
+
+                let mut stream = crate::meter_reading::sml_message_stream(uart, registers.clone());
+                loop {
+                    let next = match settings.read_timeout {
+                        Some(read_timeout) => {
+                            match tokio::time::timeout(read_timeout, stream.next()).await {
+                                Ok(next) => next,
+                                Err(_) => {
+                                    log::warn!(
+                                        "Meter {}: no reading for {}s",
+                                        meter.name,
+                                        read_timeout.as_secs()
+                                    );
+                                    break;
+                                },
+                            }
+                        },
+                        None => stream.next().await,
+                    };
+                    let Some(reading) = next else {
+                        break;
+                    };
+                    delay = serial_config.reconnect_delay();
+
+                    let publisher = match &mut publisher {
+                        Some(publisher) => publisher,
+                        None => {
+                            let Some(server_id) = &reading.server_id else {
+                                bail!(
+                                    "The topic prefix {} contains {{server_id}}, but the meter \
+                                     sends none",
+                                    mqtt_config.topic_prefix
+                                );
+                            };
+                            mqtt_config.resolve_topic_prefix(server_id);
+                            publisher.insert(connect_mqtt(
+                                &meter,
+                                &mqtt_config,
+                                &mqtt_state,
+                                &registers,
+                            )?)
+                        },
+                    };
+                    let received = ReceivedReading {
+                        meter: meter.name.clone(),
+                        reading,
+                        received_at: Utc::now(),
+                    };
+
+                    publisher.set_status(Status::Online);
+                    // Logged by the connection, failures are only counted.
+                    if let Err(e) = publisher.publish_data(&received) {
+                        log::debug!("Meter {}: {e:#}", meter.name);
+                    }
+                    if let Some(database_tx) = &database_tx {
+                        if database_tx.send(received.clone()).await.is_err() {
+                            log::error!("Database writer stopped, reading is not stored");
+                        }
+                    }
+                    latest_reading_tx.send_replace(Some(received));
+                }
+
+                if !source.can_reopen() {
+                    log::info!("Meter {}: {port} ended", meter.name);
+                    if let Some(publisher) = &publisher {
+                        publisher.set_status(Status::Offline);
+                    }
+                    return Ok(());
+                }
+                log::warn!(
+                    "Meter {}: lost connection to {port}, reconnecting in {}s",
+                    meter.name,
+                    delay.as_secs()
+                );
+            },
+            Err(e) => {
+                log::warn!(
+                    "Meter {}: {e:#}, retrying in {}s",
+                    meter.name,
+                    delay.as_secs()
+                );
+            },
+        }
+
+        if let Some(publisher) = &publisher {
+            publisher.set_status(Status::Offline);
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(serial_config.reconnect_max_delay());
     }
-    if let Some(value) = reading.line_two {
-        publish_field(mqtt_client, format!("{MQTT_TOPIC_PREFIX}/l2"), value).await?;
+}
+
+/// Opens the source of `meter` and returns a description of it along with
+/// the reader.
+///
+/// The reader records everything read if the meter has a record file.
+async fn open_meter(
+    meter: &MeterConfig,
+    source: &Source,
+    settings: &LineSettings,
+) -> Result<(String, SourceReader), Error> {
+    let (description, reader) = source::open(source, settings).await?;
+
+    match &meter.record {
+        Some(path) => Ok((description, Box::new(Recorder::new(reader, path)?))),
+        None => Ok((description, reader)),
     }
-    if let Some(value) = reading.line_three {
-        publish_field(mqtt_client, format!("{MQTT_TOPIC_PREFIX}/l3"), value).await?;
+}
+
+/// Connects the meter to the MQTT broker.
+fn connect_mqtt(
+    meter: &MeterConfig,
+    mqtt_config: &MqttConfig,
+    mqtt_state: &Arc<MqttState>,
+    registers: &RegisterTable,
+) -> Result<Publisher, Error> {
+    log::info!(
+        "Publishing readings of meter {} below {}",
+        meter.name,
+        mqtt_config.topic_prefix
+    );
+
+    Publisher::connect(
+        &meter.name,
+        mqtt_config.clone(),
+        mqtt_state.clone(),
+        registers,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        mqtt: MqttArgs,
     }
 
-    Ok(())
+    #[test]
+    fn mqtt_args_precedence() {
+        // Only this test parses these variables.
+        std::env::set_var("POWER_METER_MQTT_HOST", "env-broker");
+        std::env::set_var("POWER_METER_MQTT_PORT", "1884");
+
+        let mut config: MqttConfig = toml::from_str(
+            "host = \"file-broker\"\nport = 1885\nclient_id = \"file-client\"\npassword = \
+             \"secret\"",
+        )
+        .unwrap();
+        let cli = Cli::parse_from([
+            "power-meter",
+            "--mqtt-host",
+            "flag-broker",
+            "--mqtt-password-file",
+            "/run/secrets/mqtt",
+        ]);
+        cli.mqtt.apply(&mut config);
+
+        assert_eq!(config.host, "flag-broker");
+        assert_eq!(config.port, 1884);
+        assert_eq!(config.client_id, "file-client");
+        assert_eq!(config.qos, 1);
+        // The password file replaces the password of the file.
+        assert_eq!(config.password, None);
+        assert_eq!(
+            config.password_file,
+            Some(PathBuf::from("/run/secrets/mqtt"))
+        );
+    }
 }
@@ -0,0 +1,1392 @@
+use std::{collections::BTreeMap,
+          fmt::Display,
+          fs,
+          net::{IpAddr, Ipv4Addr, SocketAddr},
+          path::{Path, PathBuf},
+          time::Duration};
+
+use anyhow::{anyhow, bail, Context, Error};
+use serde::Deserialize;
+
+use crate::{meter_reading::ObisValue,
+            mqtt::{self, PayloadFormat, ProtocolVersion, PublishPolicy},
+            obis_code::ObisCode,
+            register::{Register, RegisterTable},
+            serial::{FlowControl, LineSettings, Parity},
+            server_id::ServerId,
+            source::{Source, SourceUrl}};
+
+/// File name looked up in the user's configuration directory when no
+/// explicit `--config` path is given (e.g.
+/// `~/.config/power-meter/config.toml`).
+const DEFAULT_CONFIG_FILE: &str = "power-meter/config.toml";
+
+/// File name of the database in the user's data directory (e.g.
+/// `~/.local/share/power-meter/readings.db`).
+const DEFAULT_DATABASE_FILE: &str = "power-meter/readings.db";
+
+/// Name of the meter given with `--port`.
+pub const DEFAULT_METER_NAME: &str = "meter";
+
+/// Placeholder in `mqtt.topic_prefix` which is replaced by the server id of
+/// the meter, e.g. `power-meter/{server_id}`.
+const SERVER_ID_PLACEHOLDER: &str = "{server_id}";
+
+/// Runtime configuration read from a TOML file.
+///
+/// Every value has a default, so a missing file or missing sections yield a
+/// usable configuration. Command line flags and `POWER_METER_*` environment
+/// variables take precedence over the file, see `StartCommand`.
+///
+/// ```toml
+/// [mqtt]
+/// host = "10.15.40.33"
+/// client_id = "HL-3-RZ-POWER-01"
+/// topic_prefix = "power-meter/{server_id}"
+///
+/// [[meters]]
+/// name = "grid"
+/// port = "/dev/ttyUSB0"
+///
+/// [[meters]]
+/// name = "pv"
+/// usb = { vid = 0x10c4, pid = 0xea60, serial = "0001" }
+/// serial = { parity = "even", data_bits = 7 }
+///
+/// [[meters]]
+/// name = "heat_pump"
+/// source = "tcp://10.15.40.50:8888"
+///
+/// [[registers]]
+/// obis = "1-0:2.8.1"
+/// name = "energy_export_tariff_one"
+/// unit = "Wh"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub mqtt:      MqttConfig,
+    pub server:    ServerConfig,
+    pub database:  DatabaseConfig,
+    pub serial:    SerialConfig,
+    /// Meters read concurrently, each on its own serial port.
+    pub meters:    Vec<MeterConfig>,
+    /// Registers captured in addition to (or instead of) the built-in ones.
+    pub registers: Vec<Register>,
+}
+
+/// A meter and the source of its bytes.
+///
+/// The serial port of the IR head is given either by its path or by the USB
+/// ids of the IR head, or replaced by a capture of a meter. `source` selects
+/// any of these, a TCP bridge or stdin by URL, see `SourceUrl`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MeterConfig {
+    /// Name of the meter in the HTTP API and the `Meter` column of the
+    /// database.
+    pub name:         String,
+    /// Serial port of the IR head, e.g. `/dev/ttyUSB0` or the stable
+    /// `/dev/serial/by-id/usb-...` path.
+    pub port:         Option<String>,
+    /// USB IR head, looked up on every (re)connect as it may get another
+    /// device path when it is plugged in again.
+    pub usb:          Option<UsbDevice>,
+    /// Source as URL, e.g. `tcp://10.0.0.5:8888` for a network IR bridge.
+    pub source:       Option<SourceUrl>,
+    /// Capture file, or plain binary dump, replayed instead of reading a
+    /// port.
+    pub replay:       Option<PathBuf>,
+    /// Speed of the replay, `1` is the original pace and `0` as fast as
+    /// possible.
+    #[serde(default = "default_replay_speed")]
+    pub replay_speed: f64,
+    /// Capture file the raw bytes of the meter are appended to.
+    pub record:       Option<PathBuf>,
+    /// Settings of the serial line.
+    #[serde(default)]
+    pub serial:       SerialLineConfig,
+    /// Prefix of the topics of this meter, by default `mqtt.topic_prefix` for
+    /// a single meter and `<mqtt.topic_prefix>/<name>` for several.
+    pub topic_prefix: Option<String>,
+}
+
+/// A USB device identified by its vendor and product id and, to tell apart
+/// several IR heads of the same model, its serial number.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UsbDevice {
+    pub vid:    u16,
+    pub pid:    u16,
+    pub serial: Option<String>,
+}
+
+/// Serial line settings of a meter, the defaults of SML read heads overridden
+/// by the given ones.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SerialLineConfig {
+    pub baud_rate:    Option<u32>,
+    /// Number of data bits (5 to 8).
+    pub data_bits:    Option<u8>,
+    pub parity:       Option<Parity>,
+    /// Number of stop bits (1 or 2).
+    pub stop_bits:    Option<u8>,
+    pub flow_control: Option<FlowControl>,
+    /// Seconds without a reading after which the port is reopened, `0`
+    /// waits forever.
+    pub read_timeout: Option<u64>,
+    /// Bytes sent after opening the port as hex string, e.g. `"2f3f210d0a"`.
+    pub wake_up:      Option<String>,
+}
+
+/// Connection and publishing settings of the MQTT broker.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttConfig {
+    pub host:                String,
+    pub port:                u16,
+    pub client_id:           String,
+    /// Version of the protocol spoken with the broker.
+    pub protocol_version:    ProtocolVersion,
+    /// Keep alive interval in seconds, `0` disables keep alive pings (at
+    /// least `5` with MQTT 5).
+    pub keep_alive:          u64,
+    pub username:            Option<String>,
+    pub password:            Option<String>,
+    /// File the password is read from instead, e.g. a Docker secret.
+    pub password_file:       Option<PathBuf>,
+    /// Prefix of every published topic, e.g. `<prefix>/power`.
+    ///
+    /// `{server_id}` is replaced by the server id of the meter, e.g.
+    /// `power-meter/{server_id}` becomes `power-meter/1-HLY03-0207-2343`.
+    pub topic_prefix:        String,
+    /// Quality of service of the published readings (0, 1 or 2).
+    pub qos:                 u8,
+    /// Whether the published readings are retained by the broker.
+    pub retain:              bool,
+    /// Layout of the published readings, raw values per subtopic, a JSON
+    /// document on `<prefix>/state` or both.
+    pub payload:             PayloadFormat,
+    /// Whether the entries which aren't in the register table are published
+    /// too, under `<prefix>/obis/<code>`.
+    pub publish_unmapped:    bool,
+    /// Whether the topics of the readings are replaced by topic aliases
+    /// after their first message, with MQTT 5 and up to the maximum of the
+    /// broker.
+    pub topic_aliases:       bool,
+    /// Seconds before the first attempt to reconnect to the broker, doubled
+    /// after every failed attempt.
+    pub reconnect_delay:     u64,
+    /// Maximum number of seconds between two attempts.
+    pub reconnect_max_delay: u64,
+    pub discovery:           DiscoveryConfig,
+    pub publish:             PublishConfig,
+    pub tls:                 TlsConfig,
+}
+
+/// TLS settings of the connection to the broker.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub enabled:     bool,
+    /// PEM bundle of the CAs the certificate of the broker is verified
+    /// against, by default the ones of the system.
+    pub ca_file:     Option<PathBuf>,
+    /// PEM certificate and private key of the client, for brokers which
+    /// authenticate clients by certificate.
+    pub cert_file:   Option<PathBuf>,
+    pub key_file:    Option<PathBuf>,
+    /// Protocols offered by ALPN, e.g. `["mqtt"]`.
+    pub alpn:        Vec<String>,
+    /// Name the certificate of the broker is verified against, by default
+    /// `mqtt.host`. Only the verification uses it, SNI still sends the host.
+    pub verify_name: Option<String>,
+}
+
+/// Home Assistant MQTT discovery of the registers of the meters.
+///
+/// Every numeric register is announced as sensor with a retained config on
+/// `<prefix>/sensor/<server id>/<register>/config`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    pub enabled: bool,
+    /// Discovery prefix Home Assistant subscribes to.
+    pub prefix:  String,
+}
+
+/// When and how the values are published, for every field and overridden per
+/// field.
+///
+/// By default every value of every reading is published.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PublishConfig {
+    /// Minimum number of seconds between two messages of a field.
+    pub min_interval:     u64,
+    /// Seconds after which a field is published even if it didn't change,
+    /// `0` for never.
+    pub max_interval:     u64,
+    /// Change of a value in its unit which is published, `0` for any.
+    pub deadband:         f64,
+    /// Change of a value in percent of the last published one which is
+    /// published, `0` for any.
+    pub deadband_percent: f64,
+    /// Seconds after which the broker drops a message, also a retained one,
+    /// `0` for never. Requires MQTT 5.
+    pub message_expiry:   u32,
+    /// Policies by field, the name of a register or `obis/<code>`.
+    pub fields:           BTreeMap<String, FieldPublishConfig>,
+}
+
+/// Publish policy of a field, unset values are taken from `PublishConfig`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FieldPublishConfig {
+    pub min_interval:     Option<u64>,
+    pub max_interval:     Option<u64>,
+    pub deadband:         Option<f64>,
+    pub deadband_percent: Option<f64>,
+    pub message_expiry:   Option<u32>,
+}
+
+impl Default for PublishConfig {
+    fn default() -> Self {
+        PublishConfig {
+            min_interval:     0,
+            max_interval:     0,
+            deadband:         0.0,
+            deadband_percent: 0.0,
+            message_expiry:   0,
+            fields:           BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig {
+            enabled: false,
+            prefix:  "homeassistant".to_string(),
+        }
+    }
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            host:                "localhost".to_string(),
+            port:                1883,
+            client_id:           "power-meter".to_string(),
+            protocol_version:    ProtocolVersion::V3_1_1,
+            keep_alive:          10,
+            username:            None,
+            password:            None,
+            password_file:       None,
+            topic_prefix:        "power-meter".to_string(),
+            qos:                 1,
+            retain:              true,
+            payload:             PayloadFormat::Raw,
+            publish_unmapped:    false,
+            topic_aliases:       true,
+            reconnect_delay:     1,
+            reconnect_max_delay: 60,
+            discovery:           DiscoveryConfig::default(),
+            publish:             PublishConfig::default(),
+            tls:                 TlsConfig::default(),
+        }
+    }
+}
+
+/// Settings of the HTTP server serving the latest reading.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address the server listens on, `0.0.0.0` for all interfaces.
+    pub bind:           IpAddr,
+    pub port:           u16,
+    /// Age in seconds after which the latest reading is considered stale and
+    /// the endpoints respond with `503`.
+    pub stale_after:    u64,
+    /// Seconds after which a query of `POST /api/query` or `GET /api/history`
+    /// is aborted.
+    pub query_timeout:  u64,
+    /// Maximum number of rows returned by `POST /api/query`.
+    pub query_max_rows: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind:           IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port:           3000,
+            stale_after:    30,
+            query_timeout:  5,
+            query_max_rows: 10000,
+        }
+    }
+}
+
+/// Settings of the SQLite database storing every reading.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub enabled:         bool,
+    /// Path of the database file.
+    pub path:            PathBuf,
+    /// Maximum number of readings written in one transaction.
+    pub batch_size:      usize,
+    /// Seconds after which collected readings are written at the latest.
+    pub flush_interval:  u64,
+    /// Whether every numeric register of a reading is stored in `Registers`
+    /// too, about 120 bytes per register and reading.
+    pub store_registers: bool,
+}
+
+/// Settings of reopening the serial ports after a failure.
+///
+/// The delay doubles after every failed attempt up to `reconnect_max_delay`
+/// and starts over once the meter sends readings again.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SerialConfig {
+    /// Seconds before the first attempt to reopen a port.
+    pub reconnect_delay:     u64,
+    /// Maximum number of seconds between two attempts.
+    pub reconnect_max_delay: u64,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        SerialConfig {
+            reconnect_delay:     1,
+            reconnect_max_delay: 60,
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            enabled:         true,
+            path:            dirs::data_dir()
+                .unwrap_or_default()
+                .join(DEFAULT_DATABASE_FILE),
+            batch_size:      60,
+            flush_interval:  60,
+            store_registers: false,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration from `path`.
+    ///
+    /// Without an explicit path the default file in the user's configuration
+    /// directory is used if it exists, otherwise the built-in defaults are
+    /// returned. An explicitly given file must exist.
+    pub fn load(path: Option<&Path>) -> Result<Self, Error> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => {
+                match Self::default_path() {
+                    Some(path) if path.exists() => path,
+                    _ => return Ok(Config::default()),
+                }
+            },
+        };
+
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+
+        toml::from_str(&content)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    /// The MQTT settings of `meter`, with its topic prefix and, if there are
+    /// several meters, a client id of its own.
+    ///
+    /// Each meter has its own connection, so the broker can mark it offline
+    /// with its Last Will independent of the other meters.
+    pub fn meter_mqtt_config(&self, meter: &MeterConfig) -> MqttConfig {
+        let mut mqtt = self.mqtt.clone();
+        if self.meters.len() > 1 {
+            mqtt.client_id = format!("{}-{}", mqtt.client_id, meter.name);
+            mqtt.topic_prefix = format!("{}/{}", mqtt.topic_prefix, meter.name);
+        }
+        if let Some(topic_prefix) = &meter.topic_prefix {
+            mqtt.topic_prefix = topic_prefix.clone();
+        }
+        mqtt
+    }
+
+    /// Meter the readings stored before the `Meter` column are assigned to,
+    /// the only configured one or the one of `--port`. `None` with several
+    /// meters, whose readings can't be told apart.
+    pub fn legacy_meter(&self) -> Option<&str> {
+        match self.meters.as_slice() {
+            [] => Some(DEFAULT_METER_NAME),
+            [meter] => Some(&meter.name),
+            _ => None,
+        }
+    }
+
+    /// The built-in registers extended by the configured ones.
+    pub fn register_table(&self) -> Result<RegisterTable, Error> {
+        RegisterTable::new(&self.registers)
+    }
+
+    /// Path of the configuration file used when none is given explicitly.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(DEFAULT_CONFIG_FILE))
+    }
+
+    /// Checks the configuration for values which would only fail later on
+    /// (or make `rumqttc` panic), so the user gets a clear error at startup.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.mqtt.validate()?;
+        self.server.validate()?;
+        self.database.validate()?;
+        self.serial.validate()?;
+
+        if self.meters.is_empty() {
+            bail!("No meter configured, use --port, --source or [[meters]]");
+        }
+        for (index, meter) in self.meters.iter().enumerate() {
+            meter.validate(index)?;
+
+            let duplicate = self.meters[..index].iter().find(|other| {
+                other.name == meter.name
+                    || (meter.source().is_exclusive() && other.source() == meter.source())
+                    || (other.record.is_some() && other.record == meter.record)
+            });
+            if let Some(other) = duplicate {
+                bail!(
+                    "meters[{index}] \"{}\" has the same name, source or record file as \"{}\"",
+                    meter.name,
+                    other.name
+                );
+            }
+        }
+
+        let registers = self.register_table()?;
+        self.mqtt.publish.validate_fields(&registers)
+    }
+}
+
+impl MqttConfig {
+    fn validate(&self) -> Result<(), Error> {
+        if self.host.trim().is_empty() {
+            bail!("mqtt.host must not be empty");
+        }
+        if self.port == 0 {
+            bail!("mqtt.port must not be 0");
+        }
+        if self.client_id.trim().is_empty() {
+            bail!("mqtt.client_id must not be empty");
+        }
+        if self.client_id.starts_with(' ') {
+            bail!("mqtt.client_id must not start with a space");
+        }
+        validate_topic_prefix("mqtt.topic_prefix", &self.topic_prefix)?;
+        if self.qos > 2 {
+            bail!("mqtt.qos must be 0, 1 or 2 (got {})", self.qos);
+        }
+        if self.password.is_some() && self.username.is_none() {
+            bail!("mqtt.password requires mqtt.username to be set");
+        }
+        if self.password_file.is_some() {
+            if self.username.is_none() {
+                bail!("mqtt.password_file requires mqtt.username to be set");
+            }
+            if self.password.is_some() {
+                bail!("mqtt.password and mqtt.password_file must not both be set");
+            }
+        }
+        if self.reconnect_delay == 0 {
+            bail!("mqtt.reconnect_delay must not be 0");
+        }
+        if self.reconnect_max_delay < self.reconnect_delay {
+            bail!("mqtt.reconnect_max_delay must not be less than mqtt.reconnect_delay");
+        }
+        if self.discovery.prefix.contains(SERVER_ID_PLACEHOLDER) {
+            bail!("mqtt.discovery.prefix must not contain {SERVER_ID_PLACEHOLDER}");
+        }
+        validate_topic_prefix("mqtt.discovery.prefix", &self.discovery.prefix)?;
+        self.publish.validate()?;
+        self.tls.validate()?;
+        if self.protocol_version == ProtocolVersion::V5 {
+            if self.keep_alive < 5 {
+                bail!(
+                    "mqtt.keep_alive must be at least 5 with MQTT 5 (got {})",
+                    self.keep_alive
+                );
+            }
+        } else if self.publish.has_message_expiry() {
+            bail!("mqtt.publish.message_expiry requires mqtt.protocol_version = \"5\"");
+        }
+
+        // Reads the password file and the certificates.
+        self.credentials()?;
+        self.transport()?;
+
+        Ok(())
+    }
+
+    /// Quality of service of the published readings.
+    pub fn qos(&self) -> rumqttc::QoS {
+        match self.qos {
+            0 => rumqttc::QoS::AtMostOnce,
+            1 => rumqttc::QoS::AtLeastOnce,
+            _ => rumqttc::QoS::ExactlyOnce,
+        }
+    }
+
+    pub fn keep_alive(&self) -> Duration { Duration::from_secs(self.keep_alive) }
+
+    pub fn reconnect_delay(&self) -> Duration { Duration::from_secs(self.reconnect_delay) }
+
+    pub fn reconnect_max_delay(&self) -> Duration { Duration::from_secs(self.reconnect_max_delay) }
+
+    /// Whether the topic prefix contains the server id of the meter, which
+    /// is only known after the first reading.
+    pub fn needs_server_id(&self) -> bool { self.topic_prefix.contains(SERVER_ID_PLACEHOLDER) }
+
+    /// Replaces the server id placeholder in the topic prefix.
+    pub fn resolve_topic_prefix(&mut self, server_id: &ServerId) {
+        self.topic_prefix = self
+            .topic_prefix
+            .replace(SERVER_ID_PLACEHOLDER, &server_id.slug());
+    }
+
+    /// Full topic of a subtopic below the configured prefix.
+    pub fn topic(&self, subtopic: &str) -> String { format!("{}/{subtopic}", self.topic_prefix) }
+
+    /// Whether `value` is published, only registers of the table unless
+    /// `publish_unmapped` is set.
+    pub fn publishes(&self, value: &ObisValue) -> bool {
+        value.name.is_some() || self.publish_unmapped
+    }
+
+    /// Options of the MQTT 3.1.1 client.
+    pub fn options(&self) -> Result<rumqttc::MqttOptions, Error> {
+        let mut options = rumqttc::MqttOptions::new(&self.client_id, &self.host, self.port);
+        options
+            .set_keep_alive(self.keep_alive())
+            .set_transport(self.transport()?);
+        if let Some((username, password)) = self.credentials()? {
+            options.set_credentials(username, password);
+        }
+        Ok(options)
+    }
+
+    /// Options of the MQTT 5 client.
+    pub fn options_v5(&self) -> Result<rumqttc::v5::MqttOptions, Error> {
+        let mut options = rumqttc::v5::MqttOptions::new(&self.client_id, &self.host, self.port);
+        options
+            .set_keep_alive(self.keep_alive())
+            .set_transport(self.transport()?);
+        if let Some((username, password)) = self.credentials()? {
+            options.set_credentials(username, password);
+        }
+        Ok(options)
+    }
+
+    /// User name and password, read from the password file if configured.
+    fn credentials(&self) -> Result<Option<(String, String)>, Error> {
+        let Some(username) = &self.username else {
+            return Ok(None);
+        };
+        let password = match &self.password_file {
+            Some(password_file) => read_password(password_file)?,
+            None => self.password.clone().unwrap_or_default(),
+        };
+        Ok(Some((username.clone(), password)))
+    }
+
+    fn transport(&self) -> Result<rumqttc::Transport, Error> {
+        if !self.tls.enabled {
+            return Ok(rumqttc::Transport::Tcp);
+        }
+        let config = mqtt::tls::client_config(&self.tls).context("Invalid mqtt.tls")?;
+        Ok(rumqttc::Transport::Tls(rumqttc::TlsConfiguration::Rustls(
+            config,
+        )))
+    }
+}
+
+impl TlsConfig {
+    fn validate(&self) -> Result<(), Error> {
+        if self.cert_file.is_some() != self.key_file.is_some() {
+            bail!("mqtt.tls.cert_file and mqtt.tls.key_file must be set together");
+        }
+        let configured = self.ca_file.is_some()
+            || self.cert_file.is_some()
+            || !self.alpn.is_empty()
+            || self.verify_name.is_some();
+        if configured && !self.enabled {
+            bail!("mqtt.tls requires mqtt.tls.enabled to be set");
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads a password file, without the line break at its end.
+fn read_password(path: &Path) -> Result<String, Error> {
+    let password = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read mqtt.password_file {}", path.display()))?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+impl PublishConfig {
+    fn validate(&self) -> Result<(), Error> {
+        validate_publish_policy("mqtt.publish", &self.default_policy())?;
+        for field in self.fields.keys() {
+            validate_publish_policy(&format!("mqtt.publish.fields.{field}"), &self.policy(field))?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that every field with a policy is a register of `registers`.
+    fn validate_fields(&self, registers: &RegisterTable) -> Result<(), Error> {
+        for field in self.fields.keys() {
+            let known = match field.strip_prefix("obis/") {
+                Some(obis_code) => obis_code.parse::<ObisCode>().is_ok(),
+                None => registers.by_name(field).is_some(),
+            };
+            if !known {
+                bail!(
+                    "mqtt.publish.fields.{field} is no register, expected its name or obis/<code>"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The policy of `field`.
+    pub fn policy(&self, field: &str) -> PublishPolicy {
+        match self.fields.get(field) {
+            Some(field) => self.merge(field),
+            None => self.default_policy(),
+        }
+    }
+
+    /// The policy of fields without one of their own.
+    pub fn default_policy(&self) -> PublishPolicy { self.merge(&FieldPublishConfig::default()) }
+
+    fn merge(&self, field: &FieldPublishConfig) -> PublishPolicy {
+        let max_interval = field.max_interval.unwrap_or(self.max_interval);
+        let message_expiry = field.message_expiry.unwrap_or(self.message_expiry);
+
+        PublishPolicy {
+            min_interval:     Duration::from_secs(field.min_interval.unwrap_or(self.min_interval)),
+            max_interval:     (max_interval > 0).then(|| Duration::from_secs(max_interval)),
+            deadband:         field.deadband.unwrap_or(self.deadband),
+            deadband_percent: field.deadband_percent.unwrap_or(self.deadband_percent),
+            message_expiry:   (message_expiry > 0).then_some(message_expiry),
+        }
+    }
+
+    /// Whether any field expires.
+    fn has_message_expiry(&self) -> bool {
+        self.message_expiry > 0
+            || self
+                .fields
+                .values()
+                .any(|field| field.message_expiry.is_some_and(|expiry| expiry > 0))
+    }
+}
+
+fn validate_publish_policy(section: &str, policy: &PublishPolicy) -> Result<(), Error> {
+    if !(policy.deadband >= 0.0 && policy.deadband.is_finite()) {
+        bail!(
+            "{section}.deadband must be a positive number or 0 (got {})",
+            policy.deadband
+        );
+    }
+    if !(policy.deadband_percent >= 0.0 && policy.deadband_percent.is_finite()) {
+        bail!(
+            "{section}.deadband_percent must be a positive number or 0 (got {})",
+            policy.deadband_percent
+        );
+    }
+    if policy
+        .max_interval
+        .is_some_and(|max_interval| max_interval < policy.min_interval)
+    {
+        bail!("{section}.max_interval must not be less than min_interval");
+    }
+
+    Ok(())
+}
+
+fn default_replay_speed() -> f64 { 1.0 }
+
+fn validate_topic_prefix(field: &str, topic_prefix: &str) -> Result<(), Error> {
+    if topic_prefix.is_empty() {
+        bail!("{field} must not be empty");
+    }
+    if topic_prefix.contains(['+', '#']) {
+        bail!("{field} must not contain the wildcards '+' or '#' (got \"{topic_prefix}\")");
+    }
+    if topic_prefix.ends_with('/') {
+        bail!("{field} must not end with '/' (got \"{topic_prefix}\")");
+    }
+    if topic_prefix
+        .replace(SERVER_ID_PLACEHOLDER, "")
+        .contains(['{', '}'])
+    {
+        bail!(
+            "{field} must not contain placeholders other than {SERVER_ID_PLACEHOLDER} (got \
+             \"{topic_prefix}\")"
+        );
+    }
+
+    Ok(())
+}
+
+impl MeterConfig {
+    fn validate(&self, index: usize) -> Result<(), Error> {
+        let valid_name = !self.name.is_empty()
+            && self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid_name {
+            bail!(
+                "meters[{index}].name \"{}\" is invalid (allowed are letters, digits, '_' and '-')",
+                self.name
+            );
+        }
+        let sources = [
+            self.port.is_some(),
+            self.usb.is_some(),
+            self.replay.is_some(),
+            self.source.is_some(),
+        ];
+        match sources.iter().filter(|source| **source).count() {
+            0 => bail!("meters[{index}] needs a port, a usb device, a replay file or a source"),
+            1 => {},
+            _ => bail!("meters[{index}] must have only one of port, usb, replay and source"),
+        }
+        if self
+            .port
+            .as_ref()
+            .is_some_and(|port| port.trim().is_empty())
+        {
+            bail!("meters[{index}].port must not be empty");
+        }
+        if !self.replay_speed.is_finite() || self.replay_speed < 0.0 {
+            bail!(
+                "meters[{index}].replay_speed must be 0 or positive (got {})",
+                self.replay_speed
+            );
+        }
+        if let Some(topic_prefix) = &self.topic_prefix {
+            validate_topic_prefix(&format!("meters[{index}].topic_prefix"), topic_prefix)?;
+        }
+        self.serial
+            .settings()
+            .with_context(|| format!("meters[{index}].serial is invalid"))?;
+
+        Ok(())
+    }
+
+    /// The source of the meter, whichever way it is configured.
+    ///
+    /// A replay runs at `replay_speed` unless its URL sets a speed.
+    pub fn source(&self) -> Source {
+        if let Some(source) = &self.source {
+            return match &source.source {
+                Source::File { path, .. } if source.speed.is_none() => {
+                    Source::File {
+                        path:  path.clone(),
+                        speed: self.replay_speed,
+                    }
+                },
+                source => source.clone(),
+            };
+        }
+        match (&self.port, &self.usb, &self.replay) {
+            (Some(port), ..) => Source::Serial(port.clone()),
+            (None, Some(usb), _) => Source::Usb(usb.clone()),
+            (None, None, Some(replay)) => {
+                Source::File {
+                    path:  replay.clone(),
+                    speed: self.replay_speed,
+                }
+            },
+            // Rejected by `validate`.
+            (None, None, None) => Source::Stdin,
+        }
+    }
+
+    /// Moves the serial settings of the source URL to `serial`, where they
+    /// override the configured ones.
+    pub fn apply_source_settings(&mut self) {
+        if let Some(source) = &mut self.source {
+            self.serial.merge(std::mem::take(&mut source.settings));
+        }
+    }
+}
+
+impl SerialLineConfig {
+    /// Overrides the settings with the ones given in `other`.
+    pub fn merge(&mut self, other: SerialLineConfig) {
+        self.baud_rate = other.baud_rate.or(self.baud_rate);
+        self.data_bits = other.data_bits.or(self.data_bits);
+        self.parity = other.parity.or(self.parity);
+        self.stop_bits = other.stop_bits.or(self.stop_bits);
+        self.flow_control = other.flow_control.or(self.flow_control);
+        self.read_timeout = other.read_timeout.or(self.read_timeout);
+        self.wake_up = other.wake_up.or(self.wake_up.take());
+    }
+
+    /// The default settings overridden by the configured ones.
+    pub fn settings(&self) -> Result<LineSettings, Error> {
+        let mut settings = LineSettings::default();
+
+        if let Some(baud_rate) = self.baud_rate {
+            if baud_rate == 0 {
+                bail!("baud_rate must not be 0");
+            }
+            settings.baud_rate = baud_rate;
+        }
+        if let Some(data_bits) = self.data_bits {
+            settings.data_bits = data_bits
+                .try_into()
+                .map_err(|()| anyhow!("data_bits must be 5, 6, 7 or 8 (got {data_bits})"))?;
+        }
+        if let Some(parity) = self.parity {
+            settings.parity = parity;
+        }
+        if let Some(stop_bits) = self.stop_bits {
+            settings.stop_bits = stop_bits
+                .try_into()
+                .map_err(|()| anyhow!("stop_bits must be 1 or 2 (got {stop_bits})"))?;
+        }
+        if let Some(flow_control) = self.flow_control {
+            settings.flow_control = flow_control;
+        }
+        if let Some(read_timeout) = self.read_timeout {
+            settings.read_timeout = (read_timeout > 0).then(|| Duration::from_secs(read_timeout));
+        }
+        if let Some(wake_up) = &self.wake_up {
+            settings.wake_up = parse_hex(wake_up)
+                .with_context(|| format!("wake_up \"{wake_up}\" is no hex string"))?;
+        }
+
+        Ok(settings)
+    }
+}
+
+/// Parses a hex string, whitespace between the bytes is ignored.
+fn parse_hex(hex: &str) -> Result<Vec<u8>, Error> {
+    let digits: Vec<char> = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.len() % 2 != 0 {
+        bail!("odd number of digits");
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let byte: String = pair.iter().collect();
+            // `from_str_radix` would accept a sign, e.g. `+1`.
+            if !pair.iter().all(char::is_ascii_hexdigit) {
+                bail!("invalid byte {byte}");
+            }
+            u8::from_str_radix(&byte, 16).with_context(|| format!("invalid byte {byte}"))
+        })
+        .collect()
+}
+
+impl Display for UsbDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "USB {:04x}:{:04x}", self.vid, self.pid)?;
+        if let Some(serial) = &self.serial {
+            write!(f, " (serial {serial})")?;
+        }
+        Ok(())
+    }
+}
+
+impl ServerConfig {
+    fn validate(&self) -> Result<(), Error> {
+        if self.port == 0 {
+            bail!("server.port must not be 0");
+        }
+        if self.query_timeout == 0 {
+            bail!("server.query_timeout must not be 0");
+        }
+        if self.query_max_rows == 0 {
+            bail!("server.query_max_rows must not be 0");
+        }
+
+        Ok(())
+    }
+
+    pub fn address(&self) -> SocketAddr { SocketAddr::new(self.bind, self.port) }
+
+    pub fn stale_after(&self) -> Duration { Duration::from_secs(self.stale_after) }
+
+    pub fn query_timeout(&self) -> Duration { Duration::from_secs(self.query_timeout) }
+}
+
+impl DatabaseConfig {
+    fn validate(&self) -> Result<(), Error> {
+        if !self.enabled {
+            return Ok(());
+        }
+        if self.path.as_os_str().is_empty() {
+            bail!("database.path must not be empty");
+        }
+        if self.batch_size == 0 {
+            bail!("database.batch_size must not be 0");
+        }
+        if self.flush_interval == 0 {
+            bail!("database.flush_interval must not be 0");
+        }
+
+        Ok(())
+    }
+
+    pub fn flush_interval(&self) -> Duration { Duration::from_secs(self.flush_interval) }
+}
+
+impl SerialConfig {
+    fn validate(&self) -> Result<(), Error> {
+        if self.reconnect_delay == 0 {
+            bail!("serial.reconnect_delay must not be 0");
+        }
+        if self.reconnect_max_delay < self.reconnect_delay {
+            bail!("serial.reconnect_max_delay must not be less than serial.reconnect_delay");
+        }
+
+        Ok(())
+    }
+
+    pub fn reconnect_delay(&self) -> Duration { Duration::from_secs(self.reconnect_delay) }
+
+    pub fn reconnect_max_delay(&self) -> Duration { Duration::from_secs(self.reconnect_max_delay) }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_serial::{DataBits, StopBits};
+
+    use super::*;
+
+    const METER: &str = "[[meters]]\nname = \"grid\"\nport = \"/dev/ttyUSB0\"\n";
+
+    fn parse(toml: &str) -> Result<Config, Error> { Ok(toml::from_str(toml)?) }
+
+    /// The error of validating `toml`, which must parse.
+    fn invalid(toml: &str) -> String {
+        let config = parse(toml).unwrap();
+        format!("{:#}", config.validate().unwrap_err())
+    }
+
+    #[test]
+    fn parse_config() {
+        let config = parse(
+            r#"
+            [mqtt]
+            host = "broker"
+            client_id = "house"
+            topic_prefix = "power/{server_id}"
+            protocol_version = "5"
+
+            [mqtt.publish.fields.power]
+            min_interval = 10
+
+            [database]
+            store_registers = true
+
+            [[meters]]
+            name = "grid"
+            usb = { vid = 0x10c4, pid = 0xea60, serial = "0001" }
+            serial = { parity = "even", data_bits = 7 }
+
+            [[meters]]
+            name = "pv"
+            source = "tcp://10.0.0.5:8888"
+
+            [[registers]]
+            obis = "1-0:2.8.1"
+            name = "energy_export_tariff_one"
+            unit = "Wh"
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        assert_eq!(config.mqtt.host, "broker");
+        assert_eq!(config.mqtt.port, 1883);
+        assert_eq!(config.mqtt.protocol_version, ProtocolVersion::V5);
+        assert_eq!(
+            config.mqtt.publish.policy("power").min_interval,
+            Duration::from_secs(10)
+        );
+        assert!(config.database.store_registers);
+        assert_eq!(config.meters.len(), 2);
+        assert_eq!(
+            config.meters[0].usb,
+            Some(UsbDevice {
+                vid:    0x10c4,
+                pid:    0xea60,
+                serial: Some("0001".to_string()),
+            })
+        );
+        assert_eq!(config.meters[0].serial.parity, Some(Parity::Even));
+        assert_eq!(config.meters[1].source(), Source::Tcp {
+            host: "10.0.0.5".to_string(),
+            port: 8888,
+        });
+        assert!(config
+            .register_table()
+            .unwrap()
+            .by_name("energy_export_tariff_one")
+            .is_some());
+    }
+
+    #[test]
+    fn parse_rejects_unknown_fields() {
+        for toml in [
+            "color = \"blue\"",
+            "[mqtt]\nhostname = \"broker\"",
+            "[mqtt.tls]\nca = \"ca.pem\"",
+            "[mqtt.publish.fields.power]\ninterval = 10",
+            "[server]\naddress = \"0.0.0.0\"",
+            "[database]\nfile = \"readings.db\"",
+            "[serial]\ndelay = 1",
+            "[[meters]]\nname = \"grid\"\ndevice = \"/dev/ttyUSB0\"",
+            "[[meters]]\nname = \"grid\"\nserial = { preset = \"sml\" }",
+            "[[meters]]\nname = \"grid\"\nusb = { vid = 1, pid = 2, serial_number = \"3\" }",
+        ] {
+            let error = format!("{:#}", parse(toml).unwrap_err());
+            assert!(error.contains("unknown field"), "{toml}: {error}");
+        }
+    }
+
+    #[test]
+    fn meter_mqtt_config() {
+        let mut config = parse(METER).unwrap();
+        let mqtt = config.meter_mqtt_config(&config.meters[0]);
+        assert_eq!(mqtt.client_id, "power-meter");
+        assert_eq!(mqtt.topic_prefix, "power-meter");
+
+        config = parse(&format!(
+            "{METER}\n[[meters]]\nname = \"pv\"\nport = \"/dev/ttyUSB1\"\ntopic_prefix = \"pv\""
+        ))
+        .unwrap();
+        let grid = config.meter_mqtt_config(&config.meters[0]);
+        assert_eq!(grid.client_id, "power-meter-grid");
+        assert_eq!(grid.topic_prefix, "power-meter/grid");
+        let pv = config.meter_mqtt_config(&config.meters[1]);
+        assert_eq!(pv.client_id, "power-meter-pv");
+        assert_eq!(pv.topic_prefix, "pv");
+    }
+
+    #[test]
+    fn legacy_meter() {
+        let mut config = Config::default();
+        assert_eq!(config.legacy_meter(), Some(DEFAULT_METER_NAME));
+        config = parse(METER).unwrap();
+        assert_eq!(config.legacy_meter(), Some("grid"));
+        config = parse(&format!(
+            "{METER}\n[[meters]]\nname = \"pv\"\nport = \"/dev/ttyUSB1\""
+        ))
+        .unwrap();
+        assert_eq!(config.legacy_meter(), None);
+    }
+
+    #[test]
+    fn validate() {
+        parse(METER).unwrap().validate().unwrap();
+        assert_eq!(
+            format!("{:#}", Config::default().validate().unwrap_err()),
+            "No meter configured, use --port, --source or [[meters]]"
+        );
+
+        for (toml, expected) in [
+            ("[mqtt]\nhost = \" \"", "mqtt.host must not be empty"),
+            ("[mqtt]\nport = 0", "mqtt.port must not be 0"),
+            (
+                "[mqtt]\nclient_id = \"\"",
+                "mqtt.client_id must not be empty",
+            ),
+            // `rumqttc` panics on client ids starting with a space.
+            (
+                "[mqtt]\nclient_id = \" power-meter\"",
+                "mqtt.client_id must not start with a space",
+            ),
+            (
+                "[mqtt]\ntopic_prefix = \"power/#\"",
+                "mqtt.topic_prefix must not contain the wildcards",
+            ),
+            ("[mqtt]\nqos = 3", "mqtt.qos must be 0, 1 or 2 (got 3)"),
+            (
+                "[mqtt]\npassword = \"secret\"",
+                "mqtt.password requires mqtt.username to be set",
+            ),
+            (
+                "[mqtt]\npassword_file = \"/run/secrets/mqtt\"",
+                "mqtt.password_file requires mqtt.username to be set",
+            ),
+            (
+                "[mqtt]\nusername = \"meter\"\npassword = \"secret\"\npassword_file = \
+                 \"/run/secrets/mqtt\"",
+                "mqtt.password and mqtt.password_file must not both be set",
+            ),
+            (
+                "[mqtt]\nreconnect_delay = 0",
+                "mqtt.reconnect_delay must not be 0",
+            ),
+            (
+                "[mqtt]\nreconnect_delay = 10\nreconnect_max_delay = 5",
+                "mqtt.reconnect_max_delay must not be less than mqtt.reconnect_delay",
+            ),
+            (
+                "[mqtt.discovery]\nprefix = \"ha/{server_id}\"",
+                "mqtt.discovery.prefix must not contain {server_id}",
+            ),
+            (
+                "[mqtt]\nprotocol_version = \"5\"\nkeep_alive = 1",
+                "mqtt.keep_alive must be at least 5 with MQTT 5 (got 1)",
+            ),
+            (
+                "[mqtt.publish]\nmessage_expiry = 60",
+                "mqtt.publish.message_expiry requires mqtt.protocol_version = \"5\"",
+            ),
+            (
+                "[mqtt.publish]\ndeadband = -1.0",
+                "mqtt.publish.deadband must be a positive number",
+            ),
+            (
+                "[mqtt.publish.fields.power]\nmin_interval = 60\nmax_interval = 30",
+                "mqtt.publish.fields.power.max_interval must not be less than min_interval",
+            ),
+            (
+                "[mqtt.publish.fields.color]\nmin_interval = 60",
+                "mqtt.publish.fields.color is no register",
+            ),
+            (
+                "[mqtt.tls]\nca_file = \"ca.pem\"",
+                "mqtt.tls requires mqtt.tls.enabled to be set",
+            ),
+            (
+                "[mqtt.tls]\nenabled = true\ncert_file = \"client.pem\"",
+                "mqtt.tls.cert_file and mqtt.tls.key_file must be set together",
+            ),
+            ("[server]\nport = 0", "server.port must not be 0"),
+            (
+                "[server]\nquery_timeout = 0",
+                "server.query_timeout must not be 0",
+            ),
+            (
+                "[server]\nquery_max_rows = 0",
+                "server.query_max_rows must not be 0",
+            ),
+            ("[database]\npath = \"\"", "database.path must not be empty"),
+            (
+                "[database]\nbatch_size = 0",
+                "database.batch_size must not be 0",
+            ),
+            (
+                "[database]\nflush_interval = 0",
+                "database.flush_interval must not be 0",
+            ),
+            (
+                "[serial]\nreconnect_delay = 0",
+                "serial.reconnect_delay must not be 0",
+            ),
+            (
+                "[serial]\nreconnect_delay = 10\nreconnect_max_delay = 5",
+                "serial.reconnect_max_delay must not be less than serial.reconnect_delay",
+            ),
+        ] {
+            assert!(
+                invalid(&format!("{toml}\n{METER}")).starts_with(expected),
+                "{toml}"
+            );
+        }
+
+        // Settings of a disabled database aren't used.
+        parse(&format!(
+            "[database]\nenabled = false\nbatch_size = 0\n{METER}"
+        ))
+        .unwrap()
+        .validate()
+        .unwrap();
+    }
+
+    #[test]
+    fn validate_meters() {
+        for (meters, expected) in [
+            (
+                "name = \"grid meter\"\nport = \"/dev/ttyUSB0\"",
+                "meters[0].name \"grid meter\" is invalid",
+            ),
+            (
+                "name = \"grid\"",
+                "meters[0] needs a port, a usb device, a replay file or a source",
+            ),
+            (
+                "name = \"grid\"\nport = \"/dev/ttyUSB0\"\nreplay = \"capture.bin\"",
+                "meters[0] must have only one of port, usb, replay and source",
+            ),
+            (
+                "name = \"grid\"\nport = \" \"",
+                "meters[0].port must not be empty",
+            ),
+            (
+                "name = \"grid\"\nreplay = \"capture.bin\"\nreplay_speed = -1.0",
+                "meters[0].replay_speed must be 0 or positive (got -1)",
+            ),
+            (
+                "name = \"grid\"\nport = \"/dev/ttyUSB0\"\ntopic_prefix = \"grid/\"",
+                "meters[0].topic_prefix must not end with '/'",
+            ),
+            (
+                "name = \"grid\"\nport = \"/dev/ttyUSB0\"\nserial = { baud_rate = 0 }",
+                "meters[0].serial is invalid: baud_rate must not be 0",
+            ),
+            (
+                "name = \"grid\"\nport = \"/dev/ttyUSB0\"\n[[meters]]\nname = \"grid\"\nport = \
+                 \"/dev/ttyUSB1\"",
+                "meters[1] \"grid\" has the same name, source or record file as \"grid\"",
+            ),
+            (
+                "name = \"grid\"\nport = \"/dev/ttyUSB0\"\n[[meters]]\nname = \"pv\"\nport = \
+                 \"/dev/ttyUSB0\"",
+                "meters[1] \"pv\" has the same name, source or record file as \"grid\"",
+            ),
+        ] {
+            assert!(
+                invalid(&format!("[[meters]]\n{meters}")).starts_with(expected),
+                "{meters}"
+            );
+        }
+    }
+
+    #[test]
+    fn validate_topic_prefixes() {
+        for topic_prefix in ["power-meter", "home/power/{server_id}", "{server_id}"] {
+            validate_topic_prefix("topic_prefix", topic_prefix).unwrap();
+        }
+        for (topic_prefix, expected) in [
+            ("", "topic_prefix must not be empty"),
+            (
+                "power/+/meter",
+                "topic_prefix must not contain the wildcards",
+            ),
+            ("power/#", "topic_prefix must not contain the wildcards"),
+            ("power/", "topic_prefix must not end with '/'"),
+            (
+                "power/{meter}",
+                "topic_prefix must not contain placeholders other than {server_id}",
+            ),
+            (
+                "power/{server_id",
+                "topic_prefix must not contain placeholders other than {server_id}",
+            ),
+        ] {
+            let error = validate_topic_prefix("topic_prefix", topic_prefix).unwrap_err();
+            assert!(
+                error.to_string().starts_with(expected),
+                "{topic_prefix}: {error}"
+            );
+        }
+    }
+
+    #[test]
+    fn serial_line_settings() {
+        let settings = SerialLineConfig::default().settings().unwrap();
+        assert_eq!(settings, LineSettings::default());
+        assert_eq!(settings.baud_rate, 9600);
+        assert_eq!(settings.read_timeout, Some(Duration::from_secs(30)));
+
+        let config = SerialLineConfig {
+            baud_rate:    Some(300),
+            data_bits:    Some(7),
+            parity:       Some(Parity::Even),
+            stop_bits:    Some(2),
+            flow_control: Some(FlowControl::Software),
+            read_timeout: Some(0),
+            wake_up:      Some("2f 3f 21 0d 0a".to_string()),
+        };
+        assert_eq!(config.settings().unwrap(), LineSettings {
+            baud_rate:    300,
+            data_bits:    DataBits::Seven,
+            parity:       Parity::Even,
+            stop_bits:    StopBits::Two,
+            flow_control: FlowControl::Software,
+            read_timeout: None,
+            wake_up:      b"/?!\r\n".to_vec(),
+        });
+
+        for (config, expected) in [
+            (
+                SerialLineConfig {
+                    data_bits: Some(9),
+                    ..Default::default()
+                },
+                "data_bits must be 5, 6, 7 or 8 (got 9)",
+            ),
+            (
+                SerialLineConfig {
+                    stop_bits: Some(0),
+                    ..Default::default()
+                },
+                "stop_bits must be 1 or 2 (got 0)",
+            ),
+            (
+                SerialLineConfig {
+                    wake_up: Some("2f3".to_string()),
+                    ..Default::default()
+                },
+                "wake_up \"2f3\" is no hex string: odd number of digits",
+            ),
+        ] {
+            assert_eq!(format!("{:#}", config.settings().unwrap_err()), expected);
+        }
+    }
+
+    #[test]
+    fn serial_line_config_merge() {
+        let mut config = SerialLineConfig {
+            baud_rate: Some(9600),
+            parity: Some(Parity::Even),
+            wake_up: Some("00".to_string()),
+            ..Default::default()
+        };
+        config.merge(SerialLineConfig {
+            baud_rate: Some(2400),
+            read_timeout: Some(5),
+            ..Default::default()
+        });
+
+        assert_eq!(config.baud_rate, Some(2400));
+        assert_eq!(config.parity, Some(Parity::Even));
+        assert_eq!(config.read_timeout, Some(5));
+        assert_eq!(config.wake_up.as_deref(), Some("00"));
+        assert_eq!(config.data_bits, None);
+    }
+
+    #[test]
+    fn parse_hex_strings() {
+        assert_eq!(parse_hex("2f3f210d0a").unwrap(), b"/?!\r\n");
+        assert_eq!(parse_hex(" 2F 3f\t21 ").unwrap(), b"/?!");
+        assert_eq!(parse_hex("").unwrap(), b"");
+        assert_eq!(
+            parse_hex("2f3").unwrap_err().to_string(),
+            "odd number of digits"
+        );
+        assert_eq!(parse_hex("2g").unwrap_err().to_string(), "invalid byte 2g");
+        assert_eq!(parse_hex("+1").unwrap_err().to_string(), "invalid byte +1");
+    }
+}
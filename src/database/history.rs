@@ -0,0 +1,401 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
+use sqlite::{Connection, State};
+
+/// A series of the history API and the column it is computed from.
+///
+/// The names match the MQTT subtopics, e.g. `power` for `<prefix>/power`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HistoryField {
+    Power,
+    LineOne,
+    LineTwo,
+    LineThree,
+    EnergyImport,
+    EnergyExport,
+}
+
+impl HistoryField {
+    pub const ALL: [HistoryField; 6] = [
+        HistoryField::Power,
+        HistoryField::LineOne,
+        HistoryField::LineTwo,
+        HistoryField::LineThree,
+        HistoryField::EnergyImport,
+        HistoryField::EnergyExport,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HistoryField::Power => "power",
+            HistoryField::LineOne => "l1",
+            HistoryField::LineTwo => "l2",
+            HistoryField::LineThree => "l3",
+            HistoryField::EnergyImport => "energy_import",
+            HistoryField::EnergyExport => "energy_export",
+        }
+    }
+
+    fn column(self) -> &'static str {
+        match self {
+            HistoryField::Power => "NetPower",
+            HistoryField::LineOne => "LineOne",
+            HistoryField::LineTwo => "LineTwo",
+            HistoryField::LineThree => "LineThree",
+            HistoryField::EnergyImport => "MeterReading",
+            HistoryField::EnergyExport => "MeterReadingOutbound",
+        }
+    }
+
+    /// Whether the field is an energy counter, which is summarized with
+    /// first/last/delta instead of min/max/avg.
+    fn is_counter(self) -> bool {
+        matches!(
+            self,
+            HistoryField::EnergyImport | HistoryField::EnergyExport
+        )
+    }
+}
+
+impl FromStr for HistoryField {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match HistoryField::ALL
+            .into_iter()
+            .find(|field| field.name() == s)
+        {
+            Some(field) => Ok(field),
+            None => bail!("Unknown field \"{s}\""),
+        }
+    }
+}
+
+/// Width of the buckets the readings are summarized in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    Minute,
+    QuarterHour,
+    Hour,
+    Day,
+}
+
+impl Resolution {
+    pub fn name(self) -> &'static str {
+        match self {
+            Resolution::Minute => "1m",
+            Resolution::QuarterHour => "15m",
+            Resolution::Hour => "1h",
+            Resolution::Day => "1d",
+        }
+    }
+
+    pub fn seconds(self) -> i64 {
+        match self {
+            Resolution::Minute => 60,
+            Resolution::QuarterHour => 15 * 60,
+            Resolution::Hour => 60 * 60,
+            Resolution::Day => 24 * 60 * 60,
+        }
+    }
+}
+
+impl FromStr for Resolution {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1m" => Ok(Resolution::Minute),
+            "15m" => Ok(Resolution::QuarterHour),
+            "1h" => Ok(Resolution::Hour),
+            "1d" => Ok(Resolution::Day),
+            _ => bail!("Unknown resolution \"{s}\", expected one of 1m, 15m, 1h or 1d"),
+        }
+    }
+}
+
+/// Downsampled series of the stored readings between `from` (inclusive) and
+/// `to` (exclusive).
+#[derive(Debug)]
+pub struct HistoryQuery {
+    pub from:       DateTime<Utc>,
+    pub to:         DateTime<Utc>,
+    pub resolution: Resolution,
+    pub fields:     Vec<HistoryField>,
+    /// Only readings of this meter, `None` for all.
+    pub meter:      Option<String>,
+}
+
+impl HistoryQuery {
+    fn sql(&self) -> String {
+        let mut readings = String::new();
+        let mut columns = String::new();
+        for field in &self.fields {
+            let column = field.column();
+            if field.is_counter() {
+                // A counter drops after a meter exchange or reset, so the
+                // first and last value of a bucket are taken by time, not as
+                // its minimum and maximum.
+                for (name, order) in [("First", "ASC"), ("Last", "DESC")] {
+                    readings += &format!(
+                        ", FIRST_VALUE({column}) OVER (PARTITION BY Meter, Timestamp / ?1 ORDER \
+                         BY {column} IS NULL, Timestamp {order}, ROWID {order}) AS {column}{name}"
+                    );
+                }
+                columns += &format!(", MAX({column}First), MAX({column}Last)");
+            } else {
+                readings += &format!(", {column}");
+                columns += &format!(", MIN({column}), MAX({column}), AVG({column})");
+            }
+        }
+
+        // Every meter has a series of its own.
+        format!(
+            "SELECT Meter, Bucket, COUNT(*){columns} FROM (SELECT Meter, (Timestamp / ?1) * ?1 AS \
+             Bucket{readings} FROM Readings WHERE Timestamp >= ?2 AND Timestamp < ?3 AND (?4 IS \
+             NULL OR Meter = ?4)) GROUP BY Meter, Bucket ORDER BY Meter, Bucket"
+        )
+    }
+
+    /// Number of buckets of the range, the number of points returned at most.
+    pub fn buckets(&self) -> i64 {
+        let seconds = self.resolution.seconds();
+        (self.to.timestamp() - 1).div_euclid(seconds) - self.from.timestamp().div_euclid(seconds)
+            + 1
+    }
+
+    /// Runs the query on `connection` and passes every bucket to `emit` as
+    /// soon as it is read, until `emit` returns `false`.
+    pub(super) fn run(
+        &self,
+        connection: &Connection,
+        mut emit: impl FnMut(Value) -> bool,
+    ) -> Result<(), sqlite::Error> {
+        let mut statement = connection.prepare(self.sql())?;
+        statement.bind((1, self.resolution.seconds()))?;
+        statement.bind((2, self.from.timestamp()))?;
+        statement.bind((3, self.to.timestamp()))?;
+        statement.bind((4, self.meter.as_deref()))?;
+
+        while statement.next()? == State::Row {
+            let mut point = Map::new();
+            point.insert(
+                "meter".to_string(),
+                json!(statement.read::<Option<String>, _>(0)?),
+            );
+            point.insert("time".to_string(), json!(statement.read::<i64, _>(1)?));
+            point.insert("count".to_string(), json!(statement.read::<i64, _>(2)?));
+
+            let mut index = 3;
+            for field in &self.fields {
+                let summary = if field.is_counter() {
+                    let first = statement.read::<Option<f64>, _>(index)?;
+                    let last = statement.read::<Option<f64>, _>(index + 1)?;
+                    index += 2;
+                    let delta = first.zip(last).map(|(first, last)| last - first);
+                    json!({ "first": first, "last": last, "delta": delta })
+                } else {
+                    let min = statement.read::<Option<f64>, _>(index)?;
+                    let max = statement.read::<Option<f64>, _>(index + 1)?;
+                    let avg = statement.read::<Option<f64>, _>(index + 2)?;
+                    index += 3;
+                    json!({ "min": min, "max": max, "avg": avg })
+                };
+                point.insert(field.name().to_string(), summary);
+            }
+
+            if !emit(Value::Object(point)) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{database::{Database, ReadonlyDatabase},
+                test_util::TempPath};
+
+    /// Stores the readings `(Timestamp, NetPower, MeterReading)` of `meter`.
+    fn insert(path: &TempPath, meter: &str, readings: &[(i64, Option<f64>, Option<f64>)]) {
+        let database = Database::open(path.path(), None).unwrap();
+        let mut statement = database
+            .connection
+            .prepare(
+                "INSERT INTO Readings (Timestamp, NetPower, MeterReading, Meter) VALUES (?, ?, ?, \
+                 ?)",
+            )
+            .unwrap();
+        for &(timestamp, power, energy) in readings {
+            statement.reset().unwrap();
+            statement.bind((1, timestamp)).unwrap();
+            statement.bind((2, power)).unwrap();
+            statement.bind((3, energy)).unwrap();
+            statement.bind((4, meter)).unwrap();
+            while statement.next().unwrap() != State::Done {}
+        }
+    }
+
+    fn query(from: i64, to: i64) -> HistoryQuery {
+        HistoryQuery {
+            from:       DateTime::from_timestamp(from, 0).unwrap(),
+            to:         DateTime::from_timestamp(to, 0).unwrap(),
+            resolution: Resolution::Minute,
+            fields:     vec![HistoryField::Power, HistoryField::EnergyImport],
+            meter:      None,
+        }
+    }
+
+    fn history(path: &TempPath, query: &HistoryQuery) -> Vec<Value> {
+        let database = ReadonlyDatabase::open(path.path(), Duration::from_secs(5), 100).unwrap();
+        let mut points = Vec::new();
+        database
+            .history(query, |point| {
+                points.push(point);
+                true
+            })
+            .unwrap();
+        points
+    }
+
+    #[test]
+    fn buckets() {
+        let path = TempPath::new("history.db");
+        insert(&path, "grid", &[
+            // Before the range.
+            (59, Some(1000.0), Some(999.0)),
+            (60, Some(100.0), Some(1000.0)),
+            (70, Some(300.0), Some(1005.0)),
+            (80, Some(200.0), None),
+            // An empty bucket is left out.
+            (200, None, Some(10.0)),
+            // After the range.
+            (240, Some(5000.0), Some(20.0)),
+        ]);
+
+        assert_eq!(history(&path, &query(60, 240)), [
+            json!({
+                "meter": "grid",
+                "time": 60,
+                "count": 3,
+                "power": { "min": 100.0, "max": 300.0, "avg": 200.0 },
+                "energy_import": { "first": 1000.0, "last": 1005.0, "delta": 5.0 },
+            }),
+            json!({
+                "meter": "grid",
+                "time": 180,
+                "count": 1,
+                "power": { "min": null, "max": null, "avg": null },
+                "energy_import": { "first": 10.0, "last": 10.0, "delta": 0.0 },
+            }),
+        ]);
+    }
+
+    #[test]
+    fn counter_by_time() {
+        let path = TempPath::new("history.db");
+        insert(&path, "grid", &[
+            // The meter was exchanged, its counter started over.
+            (125, None, Some(5.0)),
+            (120, None, Some(2000.0)),
+            (130, None, None),
+            // Readings of the same second are taken in the order they were
+            // stored.
+            (180, None, Some(11.0)),
+            (180, None, Some(12.0)),
+            (181, None, None),
+        ]);
+
+        let points = history(&path, &query(120, 240));
+        assert_eq!(
+            points[0]["energy_import"],
+            json!({ "first": 2000.0, "last": 5.0, "delta": -1995.0 })
+        );
+        assert_eq!(
+            points[1]["energy_import"],
+            json!({ "first": 11.0, "last": 12.0, "delta": 1.0 })
+        );
+    }
+
+    #[test]
+    fn counter_without_values() {
+        let path = TempPath::new("history.db");
+        insert(&path, "grid", &[(60, Some(1.0), None)]);
+
+        assert_eq!(
+            history(&path, &query(0, 120))[0]["energy_import"],
+            json!({ "first": null, "last": null, "delta": null })
+        );
+    }
+
+    #[test]
+    fn stops_emitting() {
+        let path = TempPath::new("history.db");
+        insert(&path, "grid", &[
+            (60, Some(1.0), None),
+            (120, Some(2.0), None),
+        ]);
+
+        let database = ReadonlyDatabase::open(path.path(), Duration::from_secs(5), 100).unwrap();
+        let mut points = 0;
+        database
+            .history(&query(0, 240), |_| {
+                points += 1;
+                false
+            })
+            .unwrap();
+        assert_eq!(points, 1);
+    }
+
+    #[test]
+    fn series_per_meter() {
+        let path = TempPath::new("history.db");
+        insert(&path, "pv", &[
+            (60, Some(-500.0), Some(10.0)),
+            (70, Some(-700.0), Some(20.0)),
+        ]);
+        insert(&path, "grid", &[
+            (65, Some(300.0), Some(1000.0)),
+            (75, Some(100.0), Some(1001.0)),
+        ]);
+
+        let points = history(&path, &query(0, 120));
+        assert_eq!(points, [
+            json!({
+                "meter": "grid",
+                "time": 60,
+                "count": 2,
+                "power": { "min": 100.0, "max": 300.0, "avg": 200.0 },
+                "energy_import": { "first": 1000.0, "last": 1001.0, "delta": 1.0 },
+            }),
+            json!({
+                "meter": "pv",
+                "time": 60,
+                "count": 2,
+                "power": { "min": -700.0, "max": -500.0, "avg": -600.0 },
+                "energy_import": { "first": 10.0, "last": 20.0, "delta": 10.0 },
+            }),
+        ]);
+
+        let query = HistoryQuery {
+            meter: Some("pv".to_string()),
+            ..query(0, 120)
+        };
+        assert_eq!(history(&path, &query), points[1..]);
+    }
+
+    #[test]
+    fn bucket_count() {
+        assert_eq!(query(60, 240).buckets(), 3);
+        assert_eq!(query(61, 241).buckets(), 4);
+        assert_eq!(query(0, 1).buckets(), 1);
+    }
+}
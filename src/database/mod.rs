@@ -0,0 +1,848 @@
+mod history;
+
+use std::{ffi::{c_char, c_int, c_void, CString},
+          fmt::Display,
+          fs,
+          path::{Path, PathBuf},
+          time::{Duration, Instant}};
+
+use anyhow::{Context, Error};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlite::{Connection, OpenFlags, State, Statement, Value};
+use sqlite3_sys as ffi;
+use tokio::sync::mpsc::Receiver;
+
+pub use self::history::{HistoryField, HistoryQuery, Resolution};
+use crate::{meter_reading::ReceivedReading, unit::Unit};
+
+/// Schema migrations, applied in order.
+///
+/// The number of applied migrations is stored in the `user_version` pragma,
+/// so a migration must never be changed once released. Append a new one
+/// instead.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE Readings (
+        Timestamp            INTEGER NOT NULL, -- unix time the reading was received
+        MeterTime            INTEGER,          -- seconds index of the meter
+        MeterReading         REAL,             -- total energy inbound (OBIS 1.8.0)
+        MeterReadingOutbound REAL,             -- total energy outbound (OBIS 2.8.0)
+        NetPower             REAL,             -- current net power (OBIS 16.7.0)
+        LineOne              REAL,             -- power of line one (OBIS 36.7.0)
+        LineTwo              REAL,             -- power of line two (OBIS 56.7.0)
+        LineThree            REAL              -- power of line three (OBIS 76.7.0)
+    );
+    CREATE INDEX ReadingsTimestamp ON Readings (Timestamp);
+",
+    "
+    CREATE TABLE Registers (
+        Timestamp INTEGER NOT NULL, -- unix time the reading was received
+        Obis      TEXT NOT NULL,    -- OBIS code, e.g. 1-0:32.7.0
+        Name      TEXT,             -- name in the register table
+        Value     REAL NOT NULL,    -- value with the scaler applied
+        Unit      TEXT,
+        Status    INTEGER           -- manufacturer specific status word
+    );
+    CREATE INDEX RegistersObisTimestamp ON Registers (Obis, Timestamp);
+",
+    "
+    ALTER TABLE Registers ADD COLUMN Raw INTEGER;    -- integer value sent by the meter
+    ALTER TABLE Registers ADD COLUMN Scaler INTEGER; -- Value = Raw * 10^Scaler
+",
+    "
+    ALTER TABLE Readings ADD COLUMN ServerId TEXT;  -- server id, e.g. 1 HLY03 0207 2343
+    ALTER TABLE Registers ADD COLUMN ServerId TEXT;
+",
+    "
+    ALTER TABLE Readings ADD COLUMN Meter TEXT;  -- name of the meter in the configuration
+    ALTER TABLE Registers ADD COLUMN Meter TEXT;
+",
+    "
+    UPDATE Readings SET Meter = (SELECT Meter FROM temp.Migration) WHERE Meter IS NULL;
+    UPDATE Registers SET Meter = (SELECT Meter FROM temp.Migration) WHERE Meter IS NULL;
+",
+    "
+    DROP INDEX RegistersObisTimestamp;
+    CREATE INDEX RegistersMeterObisTimestamp ON Registers (Meter, Obis, Timestamp);
+",
+];
+
+/// Milliseconds a connection waits for a lock held by another connection.
+const BUSY_TIMEOUT: usize = 5000;
+
+/// Number of SQLite virtual machine instructions between two checks of the
+/// query deadline.
+const PROGRESS_INTERVAL: c_int = 1000;
+
+/// Read-write access to the SQLite database storing the meter readings.
+pub struct Database {
+    connection:      Connection,
+    /// Whether the registers of the readings are stored in `Registers`.
+    store_registers: bool,
+}
+
+impl Database {
+    /// Opens (or creates) the database at `path` and migrates it to the
+    /// latest schema.
+    ///
+    /// The readings stored before the `Meter` column are assigned to
+    /// `legacy_meter`, they keep none if it's `None`.
+    ///
+    /// The database is switched to WAL mode, so readers (e.g. the HTTP API)
+    /// don't block the writer and vice versa.
+    pub fn open(path: &Path, legacy_meter: Option<&str>) -> Result<Self, Error> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| {
+                format!("Failed to create database directory {}", parent.display())
+            })?;
+        }
+
+        let mut connection = Connection::open(path)
+            .with_context(|| format!("Failed to open database {}", path.display()))?;
+        connection.set_busy_timeout(BUSY_TIMEOUT)?;
+        connection.execute("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")?;
+
+        let mut database = Database {
+            connection,
+            store_registers: false,
+        };
+        database.migrate(legacy_meter)?;
+
+        Ok(database)
+    }
+
+    /// Stores every numeric register of the readings in `Registers` too, at
+    /// about 120 bytes per register and reading.
+    pub fn store_registers(&mut self, store_registers: bool) {
+        self.store_registers = store_registers;
+    }
+
+    fn schema_version(&self) -> Result<usize, Error> {
+        let mut statement = self.connection.prepare("PRAGMA user_version")?;
+        statement.next()?;
+        Ok(statement.read::<i64, _>(0)? as usize)
+    }
+
+    fn migrate(&mut self, legacy_meter: Option<&str>) -> Result<(), Error> {
+        let version = self.schema_version()?;
+
+        // Values the migrations refer to, as they can't be bound to them.
+        self.connection
+            .execute("CREATE TEMP TABLE Migration (Meter TEXT)")?;
+        let mut statement = self
+            .connection
+            .prepare("INSERT INTO temp.Migration (Meter) VALUES (?)")?;
+        statement.bind((1, legacy_meter))?;
+        statement.next()?;
+        drop(statement);
+
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            self.transaction(|connection| {
+                connection.execute(migration)?;
+                connection.execute(format!("PRAGMA user_version = {}", index + 1))?;
+                Ok(())
+            })
+            .with_context(|| format!("Failed to migrate database to version {}", index + 1))?;
+        }
+
+        self.connection.execute("DROP TABLE temp.Migration")?;
+        Ok(())
+    }
+
+    fn transaction(
+        &mut self,
+        body: impl FnOnce(&Connection) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.connection.execute("BEGIN")?;
+        match body(&self.connection) {
+            Ok(()) => Ok(self.connection.execute("COMMIT")?),
+            Err(e) => {
+                let _ = self.connection.execute("ROLLBACK");
+                Err(e)
+            },
+        }
+    }
+
+    /// Inserts all `readings` in a single transaction.
+    ///
+    /// The named registers of a reading are stored in a row of `Readings`,
+    /// every numeric register additionally in `Registers` if enabled.
+    pub fn insert(&mut self, readings: &[ReceivedReading]) -> Result<(), Error> {
+        let store_registers = self.store_registers;
+        self.transaction(|connection| {
+            let mut register_statement = connection.prepare(
+                "INSERT INTO Registers (Timestamp, Obis, Name, Value, Unit, Status, Raw, Scaler, \
+                 ServerId, Meter) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )?;
+            let mut statement = connection.prepare(
+                "INSERT INTO Readings (Timestamp, MeterTime, MeterReading, MeterReadingOutbound, \
+                 NetPower, LineOne, LineTwo, LineThree, ServerId, Meter) VALUES (?, ?, ?, ?, ?, \
+                 ?, ?, ?, ?, ?)",
+            )?;
+
+            for received in readings {
+                let reading = &received.reading;
+                let server_id = reading.server_id.as_ref().map(ToString::to_string);
+                statement.reset()?;
+                statement.bind((1, received.received_at.timestamp()))?;
+                statement.bind((2, reading.meter_time.map(i64::from)))?;
+                statement.bind((3, reading.total_energy_inbound()))?;
+                statement.bind((4, reading.total_energy_outbound()))?;
+                statement.bind((5, reading.current_net_power()))?;
+                statement.bind((6, reading.line_one()))?;
+                statement.bind((7, reading.line_two()))?;
+                statement.bind((8, reading.line_three()))?;
+                statement.bind((9, server_id.as_deref()))?;
+                statement.bind((10, received.meter.as_str()))?;
+                while statement.next()? != State::Done {}
+
+                if !store_registers {
+                    continue;
+                }
+                for (obis_code, value) in &reading.values {
+                    let Some(decimal) = value.decimal() else {
+                        continue;
+                    };
+                    register_statement.reset()?;
+                    register_statement.bind((1, received.received_at.timestamp()))?;
+                    register_statement.bind((2, obis_code.to_string().as_str()))?;
+                    register_statement.bind((3, value.name.as_deref()))?;
+                    register_statement.bind((4, decimal.to_f64()))?;
+                    register_statement.bind((5, value.unit.as_ref().map(Unit::as_str)))?;
+                    // The status word is a bit field, stored with the same
+                    // bits.
+                    register_statement.bind((6, value.status.map(|status| status as i64)))?;
+                    // Only u64 values beyond i64::MAX don't fit.
+                    register_statement.bind((7, i64::try_from(decimal.raw).ok()))?;
+                    register_statement.bind((8, i64::from(decimal.scaler)))?;
+                    register_statement.bind((9, server_id.as_deref()))?;
+                    register_statement.bind((10, received.meter.as_str()))?;
+                    while register_statement.next()? != State::Done {}
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Writes the readings received on `readings` until the channel is
+    /// closed.
+    ///
+    /// Readings are collected and inserted in batches of up to `batch_size`,
+    /// or after `flush_interval` at the latest, to keep the number of writes
+    /// (and the flash wear on SD cards) low.
+    pub async fn write_readings(
+        mut self,
+        mut readings: Receiver<ReceivedReading>,
+        batch_size: usize,
+        flush_interval: Duration,
+    ) {
+        let mut batch = Vec::with_capacity(batch_size);
+        let mut flush_timer = tokio::time::interval(flush_interval);
+
+        loop {
+            let closed = tokio::select! {
+                reading = readings.recv() => match reading {
+                    Some(reading) => {
+                        batch.push(reading);
+                        if batch.len() < batch_size {
+                            continue;
+                        }
+                        false
+                    },
+                    None => true,
+                },
+                _ = flush_timer.tick() => false,
+            };
+
+            if !batch.is_empty() {
+                // SQLite is blocking, keep it from stalling the other tasks
+                // on this worker thread.
+                let result = tokio::task::block_in_place(|| self.insert(&batch));
+                if let Err(e) = result {
+                    log::error!("Failed to store {} readings: {e:?}", batch.len());
+                }
+                batch.clear();
+                flush_timer.reset();
+            }
+
+            if closed {
+                break;
+            }
+        }
+    }
+
+    pub fn metrics(&self) -> Result<DatabaseMetrics, Error> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT COUNT(*), MIN(Timestamp), MAX(Timestamp) FROM Readings")?;
+        statement.next()?;
+
+        Ok(DatabaseMetrics {
+            schema_version: self.schema_version()?,
+            readings:       statement.read::<i64, _>(0)? as usize,
+            first_reading:  read_timestamp(&statement, 1)?,
+            last_reading:   read_timestamp(&statement, 2)?,
+        })
+    }
+}
+
+fn read_timestamp(statement: &Statement, index: usize) -> Result<Option<DateTime<Utc>>, Error> {
+    let timestamp = statement.read::<Option<i64>, _>(index)?;
+    Ok(timestamp.and_then(|timestamp| DateTime::from_timestamp(timestamp, 0)))
+}
+
+/// Overview of the stored readings.
+pub struct DatabaseMetrics {
+    pub schema_version: usize,
+    pub readings:       usize,
+    pub first_reading:  Option<DateTime<Utc>>,
+    pub last_reading:   Option<DateTime<Utc>>,
+}
+
+impl Display for DatabaseMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn map_unknown(option: &Option<DateTime<Utc>>) -> String {
+            match option {
+                Some(value) => value.to_rfc3339(),
+                None => "Unknown".to_string(),
+            }
+        }
+
+        writeln!(f, "Schema Version: {}", self.schema_version)?;
+        writeln!(f, "Readings: {}", self.readings)?;
+        writeln!(f, "First Reading: {}", map_unknown(&self.first_reading))?;
+        write!(f, "Last Reading: {}", map_unknown(&self.last_reading))
+    }
+}
+
+/// Read-only access to the database for queries coming from the HTTP API.
+///
+/// Every query runs on its own connection which is opened with
+/// `SQLITE_OPEN_READONLY` and an authorizer that only permits reading, so
+/// neither writes nor `ATTACH` or `PRAGMA` statements get through. Queries are
+/// interrupted after `timeout` and return at most `max_rows` rows.
+pub struct ReadonlyDatabase {
+    path:     PathBuf,
+    timeout:  Duration,
+    max_rows: usize,
+}
+
+/// Rows returned by [`ReadonlyDatabase::query`].
+#[derive(Serialize)]
+pub struct QueryResult {
+    pub columns:   Vec<String>,
+    pub rows:      Vec<Vec<serde_json::Value>>,
+    /// Whether rows were dropped because the row limit was reached.
+    pub truncated: bool,
+}
+
+/// The error type returned by [`ReadonlyDatabase::query`] and
+/// [`ReadonlyDatabase::history`].
+#[derive(Debug)]
+pub enum QueryError {
+    /// The query took longer than the configured timeout.
+    Timeout(Duration),
+    /// The query is invalid, not permitted or failed otherwise.
+    Sqlite(sqlite::Error),
+}
+
+impl Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueryError::Timeout(timeout) => {
+                write!(
+                    f,
+                    "Query exceeded the timeout of {} ms",
+                    timeout.as_millis()
+                )
+            },
+            QueryError::Sqlite(e) => {
+                match &e.message {
+                    Some(message) => write!(f, "{message}"),
+                    None => write!(f, "{e}"),
+                }
+            },
+        }
+    }
+}
+
+impl ReadonlyDatabase {
+    /// Creates read-only access to the database at `path`.
+    ///
+    /// Fails if the database can't be opened, e.g. because the writer didn't
+    /// create it yet.
+    pub fn open(path: &Path, timeout: Duration, max_rows: usize) -> Result<Self, Error> {
+        let database = ReadonlyDatabase {
+            path: path.to_path_buf(),
+            timeout,
+            max_rows,
+        };
+        database
+            .connect()
+            .with_context(|| format!("Failed to open database {} read-only", path.display()))?;
+
+        Ok(database)
+    }
+
+    fn connect(&self) -> Result<Connection, sqlite::Error> {
+        let flags = OpenFlags::new().with_read_only().with_no_mutex();
+        let mut connection = Connection::open_with_flags(&self.path, flags)?;
+        connection.set_busy_timeout(BUSY_TIMEOUT)?;
+
+        // SAFETY: `connection` is a valid, open connection and the callback
+        // doesn't use the user data pointer.
+        unsafe {
+            ffi::sqlite3_set_authorizer(
+                connection.as_raw(),
+                Some(authorize_read_only),
+                std::ptr::null_mut(),
+            );
+        }
+
+        Ok(connection)
+    }
+
+    /// Runs a single SQL `query` and returns its rows with typed values, a
+    /// query with several statements is rejected.
+    ///
+    /// This blocks the calling thread until the query completes or times out.
+    pub fn query(&self, query: &str) -> Result<QueryResult, QueryError> {
+        self.run(|connection| self.collect_rows(connection, query))
+    }
+
+    /// Runs a history `query` and passes every bucket to `emit` until it
+    /// returns `false`.
+    ///
+    /// This blocks the calling thread until the query completes or times out,
+    /// including the time `emit` waits.
+    pub fn history(
+        &self,
+        query: &HistoryQuery,
+        emit: impl FnMut(serde_json::Value) -> bool,
+    ) -> Result<(), QueryError> {
+        self.run(|connection| query.run(connection, emit))
+    }
+
+    /// Runs `read` on a new connection which is interrupted after the timeout.
+    fn run<T>(
+        &self,
+        read: impl FnOnce(&Connection) -> Result<T, sqlite::Error>,
+    ) -> Result<T, QueryError> {
+        let connection = self.connect().map_err(QueryError::Sqlite)?;
+
+        let deadline = Instant::now() + self.timeout;
+        // SAFETY: `deadline` outlives every statement executed on
+        // `connection`, which is dropped at the end of this function.
+        unsafe {
+            ffi::sqlite3_progress_handler(
+                connection.as_raw(),
+                PROGRESS_INTERVAL,
+                Some(interrupt_after_deadline),
+                &deadline as *const Instant as *mut c_void,
+            );
+        }
+
+        let result = read(&connection);
+        drop(connection);
+
+        match result {
+            Err(e) if e.code == Some(ffi::SQLITE_INTERRUPT as isize) => {
+                Err(QueryError::Timeout(self.timeout))
+            },
+            result => result.map_err(QueryError::Sqlite),
+        }
+    }
+
+    fn collect_rows(
+        &self,
+        connection: &Connection,
+        query: &str,
+    ) -> Result<QueryResult, sqlite::Error> {
+        if has_trailing_statement(connection, query) {
+            return Err(sqlite::Error {
+                code:    Some(ffi::SQLITE_ERROR as isize),
+                message: Some("Only a single statement is allowed".to_string()),
+            });
+        }
+        let mut statement = connection.prepare(query)?;
+        let columns = statement.column_names().to_vec();
+
+        let mut rows = Vec::new();
+        let mut truncated = false;
+        while statement.next()? == State::Row {
+            if rows.len() == self.max_rows {
+                truncated = true;
+                break;
+            }
+
+            let row = (0..columns.len())
+                .map(|index| statement.read::<Value, _>(index).map(json_value))
+                .collect::<Result<_, _>>()?;
+            rows.push(row);
+        }
+
+        Ok(QueryResult {
+            columns,
+            rows,
+            truncated,
+        })
+    }
+}
+
+/// Whether `query` holds another statement after the first one, which
+/// `Connection::prepare` would silently ignore.
+fn has_trailing_statement(connection: &Connection, query: &str) -> bool {
+    // A query which can't be prepared fails with the error of `prepare`.
+    let Ok(query) = CString::new(query) else {
+        return false;
+    };
+
+    let mut remaining = query.as_ptr();
+    let mut statements = 0;
+    loop {
+        let mut statement = std::ptr::null_mut();
+        let mut tail = std::ptr::null();
+        // SAFETY: `remaining` points into the NUL terminated `query` and the
+        // statement is finalized right away, finalizing NULL is a no-op.
+        let code = unsafe {
+            let code = ffi::sqlite3_prepare_v2(
+                connection.as_raw(),
+                remaining,
+                -1,
+                &mut statement,
+                &mut tail,
+            );
+            ffi::sqlite3_finalize(statement);
+            code
+        };
+        if code != ffi::SQLITE_OK {
+            return statements > 0;
+        }
+        if !statement.is_null() {
+            statements += 1;
+            if statements > 1 {
+                return true;
+            }
+        }
+        // SAFETY: `tail` points into `query`, at the latest to its NUL.
+        if tail.is_null() || tail == remaining || unsafe { *tail } == 0 {
+            return false;
+        }
+        remaining = tail;
+    }
+}
+
+fn json_value(value: Value) -> serde_json::Value {
+    match value {
+        Value::Binary(bytes) => {
+            serde_json::Value::String(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+        },
+        Value::Float(value) => {
+            serde_json::Number::from_f64(value)
+                .map(serde_json::Value::Number)
+                .unwrap_or(serde_json::Value::Null)
+        },
+        Value::Integer(value) => serde_json::Value::from(value),
+        Value::String(value) => serde_json::Value::String(value),
+        Value::Null => serde_json::Value::Null,
+    }
+}
+
+/// SQLite authorizer permitting only the actions needed to read data.
+extern "C" fn authorize_read_only(
+    _: *mut c_void,
+    action: c_int,
+    _: *const c_char,
+    _: *const c_char,
+    _: *const c_char,
+    _: *const c_char,
+) -> c_int {
+    match action {
+        ffi::SQLITE_SELECT | ffi::SQLITE_READ | ffi::SQLITE_FUNCTION | ffi::SQLITE_RECURSIVE => {
+            ffi::SQLITE_OK
+        },
+        _ => ffi::SQLITE_DENY,
+    }
+}
+
+/// SQLite progress handler interrupting the query once the deadline passed.
+extern "C" fn interrupt_after_deadline(deadline: *mut c_void) -> c_int {
+    // SAFETY: registered with a pointer to an `Instant` in `run`.
+    let deadline = unsafe { &*(deadline as *const Instant) };
+    c_int::from(Instant::now() > *deadline)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{meter_reading::tests::{reading, received},
+                test_util::TempPath};
+
+    fn count(database: &Database, table: &str) -> i64 {
+        let mut statement = database
+            .connection
+            .prepare(format!("SELECT COUNT(*) FROM {table}"))
+            .unwrap();
+        statement.next().unwrap();
+        statement.read(0).unwrap()
+    }
+
+    /// A database of schema version 1 with a single reading.
+    fn version_one(path: &TempPath) {
+        let connection = Connection::open(path.path()).unwrap();
+        connection.execute(MIGRATIONS[0]).unwrap();
+        connection
+            .execute(
+                "PRAGMA user_version = 1; INSERT INTO Readings (Timestamp, MeterTime, \
+                 MeterReading, NetPower) VALUES (1714564800, 1234567, 607447.1, 421.5)",
+            )
+            .unwrap();
+    }
+
+    fn meters(database: &Database) -> Vec<Option<String>> {
+        let mut statement = database
+            .connection
+            .prepare("SELECT Meter FROM Readings")
+            .unwrap();
+        let mut meters = Vec::new();
+        while statement.next().unwrap() == State::Row {
+            meters.push(statement.read(0).unwrap());
+        }
+        meters
+    }
+
+    #[test]
+    fn migrate_version_one() {
+        let path = TempPath::new("migrate.db");
+        version_one(&path);
+
+        let database = Database::open(path.path(), Some("grid")).unwrap();
+        assert_eq!(database.schema_version().unwrap(), MIGRATIONS.len());
+
+        let mut statement = database
+            .connection
+            .prepare("SELECT Timestamp, MeterReading, NetPower, ServerId FROM Readings")
+            .unwrap();
+        assert_eq!(statement.next().unwrap(), State::Row);
+        assert_eq!(statement.read::<i64, _>(0).unwrap(), 1714564800);
+        assert_eq!(statement.read::<f64, _>(1).unwrap(), 607447.1);
+        assert_eq!(statement.read::<f64, _>(2).unwrap(), 421.5);
+        assert_eq!(statement.read::<Option<String>, _>(3).unwrap(), None);
+        assert_eq!(meters(&database), [Some("grid".to_string())]);
+        assert_eq!(count(&database, "Registers"), 0);
+
+        let mut indexes = database
+            .connection
+            .prepare(
+                "SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'Registers'",
+            )
+            .unwrap();
+        assert_eq!(indexes.next().unwrap(), State::Row);
+        assert_eq!(
+            indexes.read::<String, _>(0).unwrap(),
+            "RegistersMeterObisTimestamp"
+        );
+        assert_eq!(indexes.next().unwrap(), State::Done);
+        drop(indexes);
+
+        // Opening it again doesn't migrate twice.
+        drop(statement);
+        drop(database);
+        let database = Database::open(path.path(), Some("pv")).unwrap();
+        assert_eq!(database.schema_version().unwrap(), MIGRATIONS.len());
+        assert_eq!(meters(&database), [Some("grid".to_string())]);
+    }
+
+    #[test]
+    fn migrate_several_meters() {
+        let path = TempPath::new("migrate.db");
+        version_one(&path);
+
+        let database = Database::open(path.path(), None).unwrap();
+        assert_eq!(meters(&database), [None]);
+    }
+
+    #[test]
+    fn insert() {
+        let path = TempPath::new("insert.db");
+        let mut database = Database::open(path.path(), Some("grid")).unwrap();
+        database.insert(&[received(reading())]).unwrap();
+
+        let mut statement = database
+            .connection
+            .prepare(
+                "SELECT Timestamp, MeterTime, MeterReading, MeterReadingOutbound, NetPower, \
+                 ServerId, Meter FROM Readings",
+            )
+            .unwrap();
+        assert_eq!(statement.next().unwrap(), State::Row);
+        assert_eq!(statement.read::<i64, _>(0).unwrap(), 1714564800);
+        assert_eq!(statement.read::<i64, _>(1).unwrap(), 1234567);
+        assert_eq!(statement.read::<f64, _>(2).unwrap(), 607447.1);
+        assert_eq!(statement.read::<Option<f64>, _>(3).unwrap(), None);
+        assert_eq!(statement.read::<f64, _>(4).unwrap(), 421.5);
+        assert_eq!(statement.read::<String, _>(5).unwrap(), "1 HLY03 0207 2343");
+        assert_eq!(statement.read::<String, _>(6).unwrap(), "grid");
+        assert_eq!(statement.next().unwrap(), State::Done);
+        drop(statement);
+        assert_eq!(count(&database, "Registers"), 0);
+
+        database.store_registers(true);
+        database.insert(&[received(reading())]).unwrap();
+        assert_eq!(count(&database, "Readings"), 2);
+        let mut statement = database
+            .connection
+            .prepare("SELECT Obis, Name, Value, Unit, Raw, Scaler FROM Registers ORDER BY Obis")
+            .unwrap();
+        let mut registers = Vec::new();
+        while statement.next().unwrap() == State::Row {
+            registers.push((
+                statement.read::<String, _>(0).unwrap(),
+                statement.read::<Option<String>, _>(1).unwrap(),
+                statement.read::<f64, _>(2).unwrap(),
+                statement.read::<Option<String>, _>(3).unwrap(),
+                statement.read::<i64, _>(4).unwrap(),
+                statement.read::<i64, _>(5).unwrap(),
+            ));
+        }
+        // The octet string isn't stored.
+        assert_eq!(registers, [
+            (
+                "1-0:1.8.0".to_string(),
+                Some("energy_import".to_string()),
+                607447.1,
+                Some("Wh".to_string()),
+                6074471,
+                -1
+            ),
+            (
+                "1-0:16.7.0".to_string(),
+                Some("power".to_string()),
+                421.5,
+                Some("W".to_string()),
+                4215,
+                -1
+            ),
+            ("1-0:96.50.1".to_string(), None, 1.0, None, 1, 0),
+        ]);
+    }
+
+    /// A database with three readings and read-only access to it.
+    fn readonly(path: &TempPath, timeout: Duration, max_rows: usize) -> ReadonlyDatabase {
+        let mut database = Database::open(path.path(), Some("grid")).unwrap();
+        database
+            .insert(&[
+                received(reading()),
+                received(reading()),
+                received(reading()),
+            ])
+            .unwrap();
+        ReadonlyDatabase::open(path.path(), timeout, max_rows).unwrap()
+    }
+
+    fn sqlite_error(result: Result<QueryResult, QueryError>) -> String {
+        match result {
+            Err(QueryError::Sqlite(e)) => e.message.unwrap_or_default(),
+            Err(e) => panic!("Expected an SQLite error, got {e}"),
+            Ok(_) => panic!("Expected an SQLite error"),
+        }
+    }
+
+    #[test]
+    fn query_rejects_writes() {
+        let path = TempPath::new("readonly.db");
+        let attached = TempPath::new("attached.db");
+        let database = readonly(&path, Duration::from_secs(5), 100);
+
+        for query in [
+            "DELETE FROM Readings".to_string(),
+            "INSERT INTO Readings (Timestamp) VALUES (1)".to_string(),
+            "UPDATE Readings SET NetPower = 0".to_string(),
+            format!(
+                "ATTACH DATABASE '{}' AS attached",
+                attached.path().display()
+            ),
+            "PRAGMA writable_schema = ON".to_string(),
+            "BEGIN".to_string(),
+            "CREATE TABLE Other (Value INTEGER)".to_string(),
+        ] {
+            let message = sqlite_error(database.query(&query));
+            assert_eq!(message, "not authorized", "{query}");
+        }
+        assert!(!attached.path().exists());
+
+        let result = database.query("SELECT COUNT(*) FROM Readings").unwrap();
+        assert_eq!(result.rows, [[serde_json::json!(3)]]);
+    }
+
+    #[test]
+    fn query_rejects_trailing_statements() {
+        let path = TempPath::new("readonly.db");
+        let database = readonly(&path, Duration::from_secs(5), 100);
+
+        for query in ["SELECT 1; DELETE FROM Readings", "SELECT 1; SELECT 2"] {
+            let message = sqlite_error(database.query(query));
+            assert_eq!(message, "Only a single statement is allowed", "{query}");
+        }
+
+        // Trailing semicolons, whitespace and comments are no statements.
+        let result = database.query("SELECT 1; -- one\n ;").unwrap();
+        assert_eq!(result.rows, [[serde_json::json!(1)]]);
+    }
+
+    #[test]
+    fn query_max_rows() {
+        let path = TempPath::new("readonly.db");
+        let database = readonly(&path, Duration::from_secs(5), 2);
+
+        let result = database
+            .query("SELECT Meter, NetPower FROM Readings")
+            .unwrap();
+        assert_eq!(result.columns, ["Meter", "NetPower"]);
+        assert_eq!(result.rows.len(), 2);
+        assert!(result.truncated);
+
+        let result = database
+            .query("SELECT Meter FROM Readings LIMIT 2")
+            .unwrap();
+        assert_eq!(result.rows.len(), 2);
+        assert!(!result.truncated);
+    }
+
+    #[test]
+    fn query_timeout() {
+        let path = TempPath::new("readonly.db");
+        let database = readonly(&path, Duration::from_millis(100), 100);
+
+        let result = database.query(
+            "WITH RECURSIVE Numbers(N) AS (SELECT 1 UNION ALL SELECT N + 1 FROM Numbers) SELECT \
+             COUNT(*) FROM Numbers",
+        );
+        assert!(matches!(result, Err(QueryError::Timeout(_))));
+
+        // Every query gets its own deadline.
+        assert!(database.query("SELECT 1").is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn write_readings_on_close() {
+        let path = TempPath::new("write.db");
+        let database = Database::open(path.path(), Some("grid")).unwrap();
+        let (sender, receiver) = mpsc::channel(10);
+
+        let writer =
+            tokio::spawn(database.write_readings(receiver, 100, Duration::from_secs(3600)));
+        for _ in 0..3 {
+            sender.send(received(reading())).await.unwrap();
+        }
+        drop(sender);
+        writer.await.unwrap();
+
+        let database = Database::open(path.path(), Some("grid")).unwrap();
+        assert_eq!(count(&database, "Readings"), 3);
+    }
+}
@@ -0,0 +1,101 @@
+use std::fmt::Display;
+
+use serde::{Serialize, Serializer};
+
+/// A decimal number as sent by the meter: the raw integer value and the
+/// power of ten it is scaled by, `raw * 10^scaler`.
+///
+/// Keeping both instead of an `f64` allows reporting e.g. energy totals with
+/// exactly the digits of the meter, without any floating point drift.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Decimal {
+    pub raw:    i128,
+    pub scaler: i8,
+}
+
+impl Decimal {
+    pub fn new(raw: impl Into<i128>, scaler: i8) -> Self {
+        Decimal {
+            raw: raw.into(),
+            scaler,
+        }
+    }
+
+    /// The closest `f64` to the exact value.
+    pub fn to_f64(self) -> f64 {
+        // Parsing the exact decimal representation rounds only once, unlike
+        // multiplying with an inexact power of ten.
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+}
+
+/// Formats the exact value with as many fractional digits as the scaler
+/// defines, e.g. `8391649.10` for a raw value of `839164910` with scaler `-2`.
+impl Display for Decimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.raw < 0 { "-" } else { "" };
+        let digits = self.raw.unsigned_abs().to_string();
+
+        if self.scaler >= 0 {
+            if self.raw == 0 {
+                return write!(f, "0");
+            }
+            let zeros = "0".repeat(self.scaler as usize);
+            return write!(f, "{sign}{digits}{zeros}");
+        }
+
+        let fraction_digits = self.scaler.unsigned_abs() as usize;
+        let digits = format!("{digits:0>width$}", width = fraction_digits + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - fraction_digits);
+        write!(f, "{sign}{integer}.{fraction}")
+    }
+}
+
+/// Serialized as JSON number, use `raw` and `scaler` for the exact value.
+impl Serialize for Decimal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.to_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        for (raw, scaler, expected) in [
+            (839164910, -2, "8391649.10"),
+            (-5, -1, "-0.5"),
+            (5, -2, "0.05"),
+            (-5, -3, "-0.005"),
+            (42, 0, "42"),
+            (42, 3, "42000"),
+            (-42, 2, "-4200"),
+            (0, 0, "0"),
+            (0, 3, "0"),
+            (0, -2, "0.00"),
+            (i64::MIN, 0, "-9223372036854775808"),
+            (i64::MIN, -3, "-9223372036854775.808"),
+        ] {
+            assert_eq!(
+                Decimal::new(raw, scaler).to_string(),
+                expected,
+                "{raw} {scaler}"
+            );
+        }
+    }
+
+    #[test]
+    fn to_f64() {
+        assert_eq!(Decimal::new(-5, -1).to_f64(), -0.5);
+        assert_eq!(Decimal::new(5, -2).to_f64(), 0.05);
+        assert_eq!(Decimal::new(12, 3).to_f64(), 12000.0);
+        assert_eq!(Decimal::new(0, 3).to_f64(), 0.0);
+        assert_eq!(Decimal::new(0, -2).to_f64(), 0.0);
+        assert_eq!(Decimal::new(i64::MIN, 0).to_f64(), i64::MIN as f64);
+        assert_eq!(Decimal::new(i64::MIN, -18).to_f64(), -9.223372036854776);
+        // Unlike 3.0 * 0.1, which is 0.30000000000000004.
+        assert_eq!(Decimal::new(3, -1).to_f64(), 0.3);
+    }
+}
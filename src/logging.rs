@@ -0,0 +1,199 @@
+use std::{io::Write, os::unix::net::UnixDatagram, sync::Mutex};
+
+use anyhow::{anyhow, Context, Error};
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::json;
+use syslog::{Facility, Formatter3164, LoggerBackend};
+
+/// Name of the process in syslog and journald.
+const IDENTIFIER: &str = "power-meter";
+
+/// Socket of the native protocol of journald.
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Levels selected by `-q` and `-v`, starting from `info`.
+const LEVELS: [LevelFilter; 6] = [
+    LevelFilter::Off,
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+const DEFAULT_LEVEL: usize = 3;
+
+/// Where the log is written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogTarget {
+    Stderr,
+    Syslog,
+    /// Native protocol of systemd-journald, with the module as `TARGET`
+    Journald,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line with timestamp, level, target and message
+    Json,
+}
+
+/// Settings of the logger.
+#[derive(Debug, Clone)]
+pub struct LogSettings {
+    /// Number of `-v` minus the number of `-q`.
+    pub verbosity: i8,
+    /// Levels per module in the syntax of `RUST_LOG`, e.g.
+    /// `power_meter::sml=debug,rumqttc=info`.
+    pub filter:    Option<String>,
+    pub target:    LogTarget,
+    pub format:    LogFormat,
+}
+
+/// Installs the logger of `settings`.
+///
+/// The messages of this crate are logged at the level selected by the
+/// verbosity, those of the dependencies at most at `warn`. The filter
+/// overrides both.
+pub fn init(settings: &LogSettings) -> Result<(), Error> {
+    let index = (DEFAULT_LEVEL as i8 + settings.verbosity).clamp(0, LEVELS.len() as i8 - 1);
+    let level = LEVELS[index as usize];
+
+    let mut builder = env_logger::Builder::new();
+    builder
+        .filter_level(level.min(LevelFilter::Warn))
+        .filter_module(env!("CARGO_CRATE_NAME"), level);
+    if let Some(filter) = &settings.filter {
+        builder.parse_filters(filter);
+    }
+    if settings.format == LogFormat::Json {
+        builder.format(|buf, record| writeln!(buf, "{}", json_record(record)));
+    }
+    let filter = builder.build();
+    let max_level = filter.filter();
+
+    let logger: Box<dyn Log> = match settings.target {
+        LogTarget::Stderr => Box::new(filter),
+        LogTarget::Syslog => {
+            let formatter = Formatter3164 {
+                facility: Facility::LOG_DAEMON,
+                hostname: None,
+                process:  IDENTIFIER.into(),
+                pid:      std::process::id(),
+            };
+            let syslog =
+                syslog::unix(formatter).map_err(|e| anyhow!("Failed to connect to syslog: {e}"))?;
+            Box::new(SyslogLogger {
+                filter,
+                format: settings.format,
+                syslog: Mutex::new(syslog),
+            })
+        },
+        LogTarget::Journald => {
+            let socket = UnixDatagram::unbound()?;
+            socket
+                .connect(JOURNALD_SOCKET)
+                .context("Failed to connect to journald")?;
+            Box::new(JournaldLogger { filter, socket })
+        },
+    };
+
+    log::set_boxed_logger(logger).context("Failed to install the logger")?;
+    log::set_max_level(max_level);
+
+    Ok(())
+}
+
+fn json_record(record: &Record) -> serde_json::Value {
+    json!({
+        "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+    })
+}
+
+struct SyslogLogger {
+    filter: env_logger::Logger,
+    format: LogFormat,
+    syslog: Mutex<syslog::Logger<LoggerBackend, Formatter3164>>,
+}
+
+impl Log for SyslogLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool { self.filter.enabled(metadata) }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let message = match self.format {
+            LogFormat::Text => format!("{}: {}", record.target(), record.args()),
+            LogFormat::Json => json_record(record).to_string(),
+        };
+
+        let Ok(mut syslog) = self.syslog.lock() else {
+            return;
+        };
+        // There's nowhere left to report a failure to.
+        let _ = match record.level() {
+            Level::Error => syslog.err(message),
+            Level::Warn => syslog.warning(message),
+            Level::Info => syslog.info(message),
+            Level::Debug | Level::Trace => syslog.debug(message),
+        };
+    }
+
+    fn flush(&self) {}
+}
+
+struct JournaldLogger {
+    filter: env_logger::Logger,
+    socket: UnixDatagram,
+}
+
+impl Log for JournaldLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool { self.filter.enabled(metadata) }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let priority = match record.level() {
+            Level::Error => "3",
+            Level::Warn => "4",
+            Level::Info => "6",
+            Level::Debug | Level::Trace => "7",
+        };
+
+        let mut datagram = Vec::new();
+        add_journald_field(&mut datagram, "PRIORITY", priority);
+        add_journald_field(&mut datagram, "MESSAGE", &record.args().to_string());
+        add_journald_field(&mut datagram, "SYSLOG_IDENTIFIER", IDENTIFIER);
+        add_journald_field(&mut datagram, "TARGET", record.target());
+        if let Some(file) = record.file() {
+            add_journald_field(&mut datagram, "CODE_FILE", file);
+        }
+        if let Some(line) = record.line() {
+            add_journald_field(&mut datagram, "CODE_LINE", &line.to_string());
+        }
+        let _ = self.socket.send(&datagram);
+    }
+
+    fn flush(&self) {}
+}
+
+/// Appends a field in the native protocol of journald, values with a line
+/// break are prefixed by their length instead of separated by `=`.
+fn add_journald_field(datagram: &mut Vec<u8>, name: &str, value: &str) {
+    datagram.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        datagram.push(b'\n');
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        datagram.push(b'=');
+    }
+    datagram.extend_from_slice(value.as_bytes());
+    datagram.push(b'\n');
+}
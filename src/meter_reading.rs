@@ -1,256 +1,327 @@
-use std::fmt::Display;
+use std::{collections::BTreeMap, fmt::Display, sync::Arc};
 
-use anyhow::{anyhow, bail, Error};
-use serde::Serialize;
-use sml_rs::parser::{common::{Time, Value},
+use anyhow::{bail, Error};
+use chrono::{DateTime, Utc};
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use sml_rs::parser::{common::{ListEntry, Status, Time, Value},
                      complete::{File, MessageBody}};
 use tokio::{io::{AsyncRead, AsyncReadExt},
             sync::mpsc::{self, Sender}};
 use tokio_stream::{wrappers::ReceiverStream, Stream};
 
-use crate::{obis_code::ObisCode, unit::Unit};
-
-#[derive(Serialize)]
+use crate::{decimal::Decimal,
+            obis_code::ObisCode,
+            register::{RegisterTable,
+                       OBIS_CURRENT_NET_POWER,
+                       OBIS_LINE_ONE,
+                       OBIS_LINE_THREE,
+                       OBIS_LINE_TWO,
+                       OBIS_TOTAL_INBOUND_COUNT,
+                       OBIS_TOTAL_OUTBOUND_COUNT},
+            server_id::ServerId,
+            unit::Unit};
+
+/// The registers with a named accessor on [`MeterReading`], with their label
+/// and the JSON field they are serialized as (for the gauge page and existing
+/// API clients).
+const NAMED_REGISTERS: [(ObisCode, &str, &str, &str); 6] = [
+    (
+        OBIS_TOTAL_INBOUND_COUNT,
+        "Total Energy Inbound",
+        "total_energy_inbound",
+        "total_energy_inbound_unit",
+    ),
+    (
+        OBIS_TOTAL_OUTBOUND_COUNT,
+        "Total Energy Outbound",
+        "total_energy_outbound",
+        "total_energy_outbound_unit",
+    ),
+    (
+        OBIS_CURRENT_NET_POWER,
+        "Current Power",
+        "current_net_power",
+        "current_net_power_unit",
+    ),
+    (OBIS_LINE_ONE, "Line One", "line_one", "line_one_unit"),
+    (OBIS_LINE_TWO, "Line Two", "line_two", "line_two_unit"),
+    (
+        OBIS_LINE_THREE,
+        "Line Three",
+        "line_three",
+        "line_three_unit",
+    ),
+];
+
+#[derive(Clone)]
 pub struct MeterReading {
+    /// Server id of the meter as sent in the open or list response.
+    pub server_id:  Option<ServerId>,
     pub meter_time: Option<u32>,
 
-    pub total_energy_inbound:      Option<f64>,
-    pub total_energy_inbound_unit: Option<Unit>,
+    /// Every entry of the list response by OBIS code.
+    pub values: BTreeMap<ObisCode, ObisValue>,
+}
 
-    pub total_energy_outbound:      Option<f64>,
-    pub total_energy_outbound_unit: Option<Unit>,
+/// A meter reading together with its meter and the time it was received.
+#[derive(Clone)]
+pub struct ReceivedReading {
+    /// Name of the meter the reading is from.
+    pub meter:       String,
+    pub reading:     MeterReading,
+    pub received_at: DateTime<Utc>,
+}
 
-    pub current_net_power:      Option<f64>,
-    pub current_net_power_unit: Option<Unit>,
+/// An entry of the list response.
+#[derive(Clone)]
+pub struct ObisValue {
+    /// Name of the register in the [`RegisterTable`], `None` for registers
+    /// which aren't in the table.
+    pub name:       Option<String>,
+    pub value:      EntryValue,
+    pub unit:       Option<Unit>,
+    /// Status word of the entry, the meaning is manufacturer specific.
+    pub status:     Option<u64>,
+    /// Seconds index of the meter at which the value was captured.
+    pub val_time:   Option<u32>,
+    /// SML type the value was sent as, e.g. `"u32"` or `"bytes"`.
+    pub value_type: &'static str,
+}
 
-    pub line_one:      Option<f64>, // watts
-    pub line_one_unit: Option<Unit>,
+impl ObisValue {
+    /// The exact value if it is a number.
+    pub fn decimal(&self) -> Option<Decimal> {
+        match self.value {
+            EntryValue::Number(value) => Some(value),
+            _ => None,
+        }
+    }
 
-    pub line_two:      Option<f64>, // watts
-    pub line_two_unit: Option<Unit>,
+    /// The value if it is a number.
+    pub fn number(&self) -> Option<f64> { self.decimal().map(Decimal::to_f64) }
+}
 
-    pub line_three:      Option<f64>, // watts
-    pub line_three_unit: Option<Unit>,
+/// Serialized with the `raw` integer and `scaler` of numbers next to the
+/// scaled `value`, so clients can reproduce the exact value.
+impl Serialize for ObisValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let decimal = self.decimal();
+
+        let mut state = serializer.serialize_struct("ObisValue", 8)?;
+        state.serialize_field("name", &self.name)?;
+        state.serialize_field("value", &self.value)?;
+        state.serialize_field("raw", &decimal.map(|decimal| decimal.raw))?;
+        state.serialize_field("scaler", &decimal.map(|decimal| decimal.scaler))?;
+        state.serialize_field("unit", &self.unit)?;
+        state.serialize_field("status", &self.status)?;
+        state.serialize_field("val_time", &self.val_time)?;
+        state.serialize_field("value_type", &self.value_type)?;
+        state.end()
+    }
 }
 
-const OBIS_TOTAL_INBOUND_COUNT: ObisCode = ObisCode::from_octet_str(&[1, 0, 1, 8, 0, 255]);
-const OBIS_TOTAL_OUTBOUND_COUNT: ObisCode = ObisCode::from_octet_str(&[1, 0, 2, 8, 0, 255]);
-const OBIS_CURRENT_NET_POWER: ObisCode = ObisCode::from_octet_str(&[1, 0, 16, 7, 0, 255]);
-const OBIS_LINE_ONE: ObisCode = ObisCode::from_octet_str(&[1, 0, 36, 7, 0, 255]);
-const OBIS_LINE_TWO: ObisCode = ObisCode::from_octet_str(&[1, 0, 56, 7, 0, 255]);
-const OBIS_LINE_THREE: ObisCode = ObisCode::from_octet_str(&[1, 0, 76, 7, 0, 255]);
+/// Value of an entry of the list response.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EntryValue {
+    /// An integer value together with the scaler of the entry.
+    Number(Decimal),
+    Bool(bool),
+    /// An octet string, e.g. the server id or a firmware version.
+    Bytes(Vec<u8>),
+}
 
-impl MeterReading {
-    pub fn parse(sml_file: File) -> Result<Self, Error> {
-        println!("SML file \"{:#?}\"", sml_file);
-        // The payload must contain 3 messages. An open response, a get list response
-        // and a close response.
-        if sml_file.messages.len() != 3 {
-            bail!("Invalid number of messages: {}", sml_file.messages.len());
+impl Display for EntryValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EntryValue::Number(value) => write!(f, "{value}"),
+            EntryValue::Bool(value) => write!(f, "{value}"),
+            EntryValue::Bytes(bytes) => {
+                for byte in bytes {
+                    write!(f, "{byte:02x}")?;
+                }
+                Ok(())
+            },
         }
+    }
+}
 
-        let list_response = &sml_file.messages[1];
-
-        let MessageBody::GetListResponse(get_list_response) = &list_response.message_body else {
-            bail!("Unexpected message type: {:?}", list_response.message_body);
-        };
-
-        let meter_time = match &get_list_response.act_sensor_time {
-            Some(Time::SecIndex(secs)) => Some(*secs),
-            _ => None,
-        };
+impl Serialize for EntryValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            EntryValue::Number(value) => value.serialize(serializer),
+            EntryValue::Bool(value) => serializer.serialize_bool(*value),
+            EntryValue::Bytes(_) => serializer.collect_str(self),
+        }
+    }
+}
 
-        let mut meter_values = MeterReading {
-            meter_time,
-            total_energy_inbound:       None,
-            total_energy_inbound_unit:  None,
-            total_energy_outbound:      None,
-            total_energy_outbound_unit: None,
-            current_net_power:          None,
-            current_net_power_unit:     None,
-            line_one:                   None,
-            line_one_unit:              None,
-            line_two:                   None,
-            line_two_unit:              None,
-            line_three:                 None,
-            line_three_unit:            None,
-        };
-
-        for entry in &get_list_response.val_list {
-            let obis_code =
-                ObisCode::try_from_octet_str(entry.obj_name).map_err(|e| anyhow!("{e:?}"));
-            let obis_code = match obis_code {
-                Ok(obis_code) => obis_code,
-                Err(e) => {
-                    println!("Invalid obis code \"{:?}\": {:?}", entry.obj_name, e);
+impl MeterReading {
+    /// Collects the values of all list responses of `sml_file`.
+    ///
+    /// The server id is taken from the first open or list response, list
+    /// responses of other server ids are skipped with a warning. The time of
+    /// the meter is taken from the first list response which contains it.
+    /// Fails if the file has no list response.
+    pub fn parse(sml_file: File, registers: &RegisterTable) -> Result<Self, Error> {
+        let mut list_responses = 0;
+        let mut server_id: Option<&[u8]> = None;
+        let mut meter_time = None;
+        let mut values = BTreeMap::new();
+
+        for message in &sml_file.messages {
+            let list_response = match &message.message_body {
+                MessageBody::GetListResponse(list_response) => list_response,
+                MessageBody::OpenResponse(open_response) => {
+                    server_id.get_or_insert(open_response.server_id);
                     continue;
                 },
+                MessageBody::CloseResponse(_) => continue,
             };
-            let unit = entry.unit.and_then(Unit::from_u8);
-
-            match obis_code {
-                OBIS_TOTAL_INBOUND_COUNT => {
-                    let value = match entry.value {
-                        Value::I8(value) => value as f64,
-                        Value::I16(value) => value as f64,
-                        Value::I32(value) => value as f64,
-                        Value::I64(value) => value as f64,
-                        Value::U8(value) => value as f64,
-                        Value::U16(value) => value as f64,
-                        Value::U32(value) => value as f64,
-                        Value::U64(value) => value as f64,
-                        _ => {
-                            println!("Non 32bit integer: {:?}", entry.value);
-                            continue;
-                        },
-                    };
-
-                    let value = if let Some(scaler) = entry.scaler {
-                        value as f64 / 10f64.powi(-scaler as i32)
-                    } else {
-                        value as f64
-                    };
-
-                    meter_values.total_energy_inbound = Some(value);
-                    meter_values.total_energy_inbound_unit = unit;
-                },
-                OBIS_TOTAL_OUTBOUND_COUNT => {
-                    let value = match entry.value {
-                        Value::I8(value) => value as f64,
-                        Value::I16(value) => value as f64,
-                        Value::I32(value) => value as f64,
-                        Value::I64(value) => value as f64,
-                        Value::U8(value) => value as f64,
-                        Value::U16(value) => value as f64,
-                        Value::U32(value) => value as f64,
-                        Value::U64(value) => value as f64,
-                        _ => {
-                            println!("Non 32bit integer: {:?}", entry.value);
-                            continue;
-                        },
-                    };
-
-                    let value = if let Some(scaler) = entry.scaler {
-                        value as f64 / 10f64.powi(-scaler as i32)
-                    } else {
-                        value as f64
-                    };
-
-                    meter_values.total_energy_outbound = Some(value);
-                    meter_values.total_energy_outbound_unit = unit;
-                },
-                OBIS_CURRENT_NET_POWER => {
-                    let value = match entry.value {
-                        Value::I8(value) => value as f64,
-                        Value::I16(value) => value as f64,
-                        Value::I32(value) => value as f64,
-                        Value::I64(value) => value as f64,
-                        Value::U8(value) => value as f64,
-                        Value::U16(value) => value as f64,
-                        Value::U32(value) => value as f64,
-                        Value::U64(value) => value as f64,
-                        _ => {
-                            // discard non-64bit integer values
-                            println!("Non 16bit integer: {:?}", entry.value);
-                            continue;
-                        },
-                    };
-
-                    let value = if let Some(scaler) = entry.scaler {
-                        value as f64 / 10f64.powi(-scaler as i32)
-                    } else {
-                        value as f64
-                    };
-
-                    meter_values.current_net_power = Some(value);
-                    meter_values.current_net_power_unit = unit;
-                },
-                OBIS_LINE_ONE => {
-                    let value = match entry.value {
-                        Value::I8(value) => value as f64,
-                        Value::I16(value) => value as f64,
-                        Value::I32(value) => value as f64,
-                        Value::I64(value) => value as f64,
-                        Value::U8(value) => value as f64,
-                        Value::U16(value) => value as f64,
-                        Value::U32(value) => value as f64,
-                        Value::U64(value) => value as f64,
-                        _ => {
-                            println!("Non 32bit integer: {:?}", entry.value);
-                            continue;
-                        },
-                    };
-
-                    meter_values.line_one = Some(value);
-                    meter_values.line_one_unit = unit;
-                },
-                OBIS_LINE_TWO => {
-                    let value = match entry.value {
-                        Value::I8(value) => value as f64,
-                        Value::I16(value) => value as f64,
-                        Value::I32(value) => value as f64,
-                        Value::I64(value) => value as f64,
-                        Value::U8(value) => value as f64,
-                        Value::U16(value) => value as f64,
-                        Value::U32(value) => value as f64,
-                        Value::U64(value) => value as f64,
-                        _ => {
-                            println!("Non 32bit integer: {:?}", entry.value);
-                            continue;
-                        },
-                    };
-
-                    meter_values.line_two = Some(value);
-                    meter_values.line_two_unit = unit;
-                },
-                OBIS_LINE_THREE => {
-                    let value = match entry.value {
-                        Value::I8(value) => value as f64,
-                        Value::I16(value) => value as f64,
-                        Value::I32(value) => value as f64,
-                        Value::I64(value) => value as f64,
-                        Value::U8(value) => value as f64,
-                        Value::U16(value) => value as f64,
-                        Value::U32(value) => value as f64,
-                        Value::U64(value) => value as f64,
-                        _ => {
-                            println!("Non 32bit integer: {:?}", entry.value);
-                            continue;
-                        },
-                    };
-
-                    meter_values.line_three = Some(value);
-                    meter_values.line_three_unit = unit;
-                },
-                _ => {
-                    // discard unknown obis codes
-                },
+
+            let file_server_id = *server_id.get_or_insert(list_response.server_id);
+            if list_response.server_id != file_server_id {
+                log::warn!(
+                    "Skipped the list response of server id {} in a file of {}",
+                    ServerId::new(list_response.server_id),
+                    ServerId::new(file_server_id)
+                );
+                continue;
+            }
+            list_responses += 1;
+
+            if meter_time.is_none() {
+                meter_time = list_response
+                    .act_sensor_time
+                    .as_ref()
+                    .map(|Time::SecIndex(secs)| *secs);
             }
+
+            for entry in &list_response.val_list {
+                if let Some((obis_code, value)) = parse_entry(entry, registers) {
+                    values.insert(obis_code, value);
+                }
+            }
+        }
+
+        if list_responses == 0 {
+            bail!("No GetListResponse in {} messages", sml_file.messages.len());
         }
 
-        Ok(meter_values)
+        Ok(MeterReading {
+            server_id: server_id.map(ServerId::new),
+            meter_time,
+            values,
+        })
     }
 
+    pub fn get(&self, obis_code: &ObisCode) -> Option<&ObisValue> { self.values.get(obis_code) }
+
+    /// The exact numeric value of the register `obis_code`.
+    pub fn decimal(&self, obis_code: &ObisCode) -> Option<Decimal> {
+        self.get(obis_code).and_then(ObisValue::decimal)
+    }
+
+    /// The numeric value of the register `obis_code`.
+    pub fn number(&self, obis_code: &ObisCode) -> Option<f64> {
+        self.get(obis_code).and_then(ObisValue::number)
+    }
+
+    /// The unit of the register `obis_code`.
+    pub fn unit(&self, obis_code: &ObisCode) -> Option<Unit> {
+        self.get(obis_code).and_then(|value| value.unit.clone())
+    }
+
+    pub fn total_energy_inbound(&self) -> Option<f64> { self.number(&OBIS_TOTAL_INBOUND_COUNT) }
+
+    pub fn total_energy_outbound(&self) -> Option<f64> { self.number(&OBIS_TOTAL_OUTBOUND_COUNT) }
+
+    pub fn current_net_power(&self) -> Option<f64> { self.number(&OBIS_CURRENT_NET_POWER) }
+
+    pub fn line_one(&self) -> Option<f64> { self.number(&OBIS_LINE_ONE) }
+
+    pub fn line_two(&self) -> Option<f64> { self.number(&OBIS_LINE_TWO) }
+
+    pub fn line_three(&self) -> Option<f64> { self.number(&OBIS_LINE_THREE) }
+
     pub fn display_compact(&self) -> String {
-        format!(
-            "{}s, {} {}, {} {}, {} {}, {} {}, {} {}, {} {}",
-            map_unknown(&self.meter_time),
-            map_unknown(&self.total_energy_inbound),
-            map_unknown(&self.total_energy_inbound_unit),
-            map_unknown(&self.total_energy_outbound),
-            map_unknown(&self.total_energy_outbound_unit),
-            map_unknown(&self.current_net_power),
-            map_unknown(&self.current_net_power_unit),
-            map_unknown(&self.line_one),
-            map_unknown(&self.line_one_unit),
-            map_unknown(&self.line_two),
-            map_unknown(&self.line_two_unit),
-            map_unknown(&self.line_three),
-            map_unknown(&self.line_three_unit)
-        )
+        let mut compact = format!("{}s", map_unknown(&self.meter_time));
+        for (obis_code, ..) in &NAMED_REGISTERS {
+            compact += &format!(
+                ", {} {}",
+                map_unknown(&self.decimal(obis_code)),
+                map_unknown(&self.unit(obis_code))
+            );
+        }
+        compact
     }
 }
 
+/// Converts an entry of a list response, `None` if it is invalid or doesn't
+/// match the expected unit of its register.
+fn parse_entry(entry: &ListEntry, registers: &RegisterTable) -> Option<(ObisCode, ObisValue)> {
+    let obis_code = match ObisCode::try_from_octet_str(entry.obj_name) {
+        Ok(obis_code) => obis_code,
+        Err(e) => {
+            log::warn!("Invalid obis code \"{:?}\": {:?}", entry.obj_name, e);
+            return None;
+        },
+    };
+    let register = registers.get(&obis_code);
+
+    let unit = entry.unit.and_then(Unit::from_u8);
+    if let Some(register) = register {
+        // Meters which send no unit would lose the values otherwise.
+        if register.unit.is_some() && entry.unit.is_some() && unit != register.unit {
+            log::warn!(
+                "Unexpected unit of {} ({obis_code}): {:?}, expected {:?}",
+                register.name,
+                entry.unit,
+                register.unit
+            );
+            return None;
+        }
+    }
+
+    // Applied to every numeric entry, a missing scaler means 10^0.
+    let scaler = entry.scaler.unwrap_or(0);
+    let (value, value_type) = match entry.value {
+        Value::Bool(value) => (EntryValue::Bool(value), "bool"),
+        Value::Bytes(bytes) => (EntryValue::Bytes(bytes.to_vec()), "bytes"),
+        Value::I8(value) => (EntryValue::Number(Decimal::new(value, scaler)), "i8"),
+        Value::I16(value) => (EntryValue::Number(Decimal::new(value, scaler)), "i16"),
+        Value::I32(value) => (EntryValue::Number(Decimal::new(value, scaler)), "i32"),
+        Value::I64(value) => (EntryValue::Number(Decimal::new(value, scaler)), "i64"),
+        Value::U8(value) => (EntryValue::Number(Decimal::new(value, scaler)), "u8"),
+        Value::U16(value) => (EntryValue::Number(Decimal::new(value, scaler)), "u16"),
+        Value::U32(value) => (EntryValue::Number(Decimal::new(value, scaler)), "u32"),
+        Value::U64(value) => (EntryValue::Number(Decimal::new(value, scaler)), "u64"),
+        Value::List(_) => {
+            log::warn!("Unsupported list value of {obis_code}");
+            return None;
+        },
+    };
+
+    let status = entry.status.as_ref().map(|status| {
+        match *status {
+            Status::Status8(status) => status as u64,
+            Status::Status16(status) => status as u64,
+            Status::Status32(status) => status as u64,
+            Status::Status64(status) => status,
+        }
+    });
+    let val_time = entry.val_time.as_ref().map(|Time::SecIndex(secs)| *secs);
+
+    let value = ObisValue {
+        name: register.map(|register| register.name.clone()),
+        value,
+        unit,
+        status,
+        val_time,
+        value_type,
+    };
+    Some((obis_code, value))
+}
+
 fn map_unknown(option: &Option<impl Display>) -> String {
     match option {
         Some(value) => format!("{}", value),
@@ -260,64 +331,71 @@ fn map_unknown(option: &Option<impl Display>) -> String {
 
 impl Display for MeterReading {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(server_id) = &self.server_id {
+            writeln!(f, "Server Id: {server_id}")?;
+        }
         writeln!(f, "Meter Time: {}", map_unknown(&self.meter_time))?;
-        writeln!(
-            f,
-            "Total Energy Inbound: {} {}",
-            map_unknown(&self.total_energy_inbound),
-            map_unknown(&self.total_energy_inbound_unit)
-        )?;
-        writeln!(
-            f,
-            "Total Energy Outbound: {} {}",
-            map_unknown(&self.total_energy_outbound),
-            map_unknown(&self.total_energy_outbound_unit)
-        )?;
-        writeln!(
-            f,
-            "Current Power: {} {}",
-            map_unknown(&self.current_net_power),
-            map_unknown(&self.current_net_power_unit)
-        )?;
-        writeln!(
-            f,
-            "Line One: {} {}",
-            map_unknown(&self.line_one),
-            map_unknown(&self.line_one_unit)
-        )?;
-        writeln!(
-            f,
-            "Line Two: {} {}",
-            map_unknown(&self.line_two),
-            map_unknown(&self.line_two_unit)
-        )?;
-        writeln!(
-            f,
-            "Line Three: {} {}",
-            map_unknown(&self.line_three),
-            map_unknown(&self.line_three_unit)
-        )
+        for (obis_code, label, ..) in &NAMED_REGISTERS {
+            writeln!(
+                f,
+                "{label}: {} {}",
+                map_unknown(&self.decimal(obis_code)),
+                map_unknown(&self.unit(obis_code))
+            )?;
+        }
+
+        for (obis_code, value) in &self.values {
+            if NAMED_REGISTERS.iter().any(|(named, ..)| named == obis_code) {
+                continue;
+            }
+            match &value.name {
+                Some(name) => write!(f, "{name} ({obis_code}): {}", value.value)?,
+                None => write!(f, "{obis_code}: {}", value.value)?,
+            }
+            match &value.unit {
+                Some(unit) => writeln!(f, " {unit}")?,
+                None => writeln!(f)?,
+            }
+        }
+
+        Ok(())
     }
 }
 
-/// Read SML message stream from a reader
+/// Serialized with the named registers as top level fields (e.g.
+/// `"line_one"` and `"line_one_unit"`) next to all `values`.
+impl Serialize for MeterReading {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("MeterReading", 15)?;
+        state.serialize_field("server_id", &self.server_id)?;
+        state.serialize_field("meter_time", &self.meter_time)?;
+        for (obis_code, _, field, unit_field) in &NAMED_REGISTERS {
+            state.serialize_field(field, &self.number(obis_code))?;
+            state.serialize_field(unit_field, &self.unit(obis_code))?;
+        }
+        state.serialize_field("values", &self.values)?;
+        state.end()
+    }
+}
+
+/// Reads the SML files of a reader, a reading per file
+///
+/// ```ignore
+/// use std::{io::Cursor, sync::Arc};
 ///
-/// ```
-/// use std::io::Cursor;
-/// use hackdose_sml_parser::message_stream::sml_message_stream;
 /// use tokio_stream::StreamExt;
 ///
-/// #[tokio::main]
-/// async fn main() {
-///     let cursor = Cursor::new(vec![0x01, 0x02, 0x03]);
-///     let mut message_stream = sml_message_stream(cursor);
-///     while let Some(message) = message_stream.next().await {
-///         println!("Message: {:?}", message);
-///     }
+/// use crate::{meter_reading::sml_message_stream, register::RegisterTable};
+///
+/// let cursor = Cursor::new(std::fs::read("testdata/sml/multi_message.bin")?);
+/// let mut readings = sml_message_stream(cursor, Arc::new(RegisterTable::default()));
+/// while let Some(reading) = readings.next().await {
+///     println!("{}", reading.display_compact());
 /// }
 /// ```
 pub fn sml_message_stream(
     mut stream: impl AsyncRead + Unpin + Send + 'static,
+    registers: Arc<RegisterTable>,
 ) -> impl Stream<Item = MeterReading> {
     let (tx, rx) = mpsc::channel::<MeterReading>(256);
 
@@ -325,11 +403,20 @@ pub fn sml_message_stream(
     let mut decoder = sml_rs::transport::Decoder::<Vec<u8>>::new();
 
     tokio::spawn(async move {
-        while let Ok(n) = stream.read(&mut buf).await {
-            if n == 0 {
-                break;
+        loop {
+            // Stop reading, and release the port, once the stream is dropped.
+            let result = tokio::select! {
+                result = stream.read(&mut buf) => result,
+                () = tx.closed() => break,
+            };
+            match result {
+                Ok(0) => break,
+                Ok(n) => emit_message(&mut decoder, &buf[..n], &registers, tx.clone()).await,
+                Err(e) => {
+                    log::warn!("Failed to read: {e}");
+                    break;
+                },
             }
-            emit_message(&mut decoder, &buf[..n], tx.clone()).await;
         }
     });
 
@@ -339,40 +426,194 @@ pub fn sml_message_stream(
 async fn emit_message<'a>(
     decoder: &'a mut sml_rs::transport::Decoder<Vec<u8>>,
     buf: &'a [u8],
+    registers: &'a RegisterTable,
     tx: Sender<MeterReading>,
-) -> Result<(), Error> {
-    let mut to_process = buf.to_vec();
+) {
+    let to_process = buf.to_vec();
     for byte in to_process {
         match decoder.push_byte(byte) {
             Ok(None) => {},
             Ok(Some(decoded_bytes)) => {
-                let result = sml_rs::parser::complete::parse(decoded_bytes);
-                let Ok(sml_file) = result else {
-                    // if self.verbose {
-                    println!("Err({:?})", result);
-                    // }
-                    continue;
+                let result = crate::sml::parse_messages(decoded_bytes);
+                let (sml_file, diagnostics) = match result {
+                    Ok(parsed) => parsed,
+                    Err(e) => {
+                        log::warn!("Invalid SML file: {e}");
+                        continue;
+                    },
                 };
-
-                let reading = MeterReading::parse(sml_file);
-                let Ok(reading) = reading else {
-                    continue;
+                for diagnostic in diagnostics {
+                    log::warn!("{diagnostic}");
+                }
+                log::trace!("{sml_file:#?}");
+
+                let reading = match MeterReading::parse(sml_file, registers) {
+                    Ok(reading) => reading,
+                    Err(e) => {
+                        log::warn!("Invalid SML file: {e}");
+                        continue;
+                    },
                 };
-                // if self.verbose {
-                println!("{}", reading.display_compact());
-                // }
+                log::debug!("{}", reading.display_compact());
                 let _ = tx.send(reading).await;
+            },
+            // Expected for the bytes before the first frame.
+            Err(e) => log::debug!("Transport error: {e:?}"),
+        }
+    }
+}
 
-                // let _ = publish_data(&reading, mqtt_client).await;
+#[cfg(test)]
+pub mod tests {
+    use chrono::TimeZone;
+    use tokio_stream::StreamExt;
 
-                // self.latest_reading.store(Some(reading));
-            },
-            Err(e) => {
-                // if self.verbose {
-                println!("Err({:?})", e);
-                // }
-            },
+    use super::*;
+
+    /// Server id `1 HLY03 0207 2343` of the example meter.
+    pub const SERVER_ID: [u8; 10] = [0x0a, 0x01, 0x48, 0x4c, 0x59, 0x03, 0x00, 0x1f, 0x9f, 0x17];
+
+    pub fn value(name: Option<&str>, value: EntryValue, unit: Option<Unit>) -> ObisValue {
+        ObisValue {
+            name: name.map(str::to_string),
+            value,
+            unit,
+            status: None,
+            val_time: None,
+            value_type: "u32",
+        }
+    }
+
+    /// A reading of the example meter with two registers of the table, an
+    /// unmapped number and an unmapped octet string.
+    pub fn reading() -> MeterReading {
+        let values = BTreeMap::from([
+            (
+                OBIS_TOTAL_INBOUND_COUNT,
+                value(
+                    Some("energy_import"),
+                    EntryValue::Number(Decimal::new(6074471, -1)),
+                    Some(Unit::WattHour),
+                ),
+            ),
+            (
+                OBIS_CURRENT_NET_POWER,
+                value(
+                    Some("power"),
+                    EntryValue::Number(Decimal::new(4215, -1)),
+                    Some(Unit::Watt),
+                ),
+            ),
+            (
+                "1-0:96.50.1".parse().unwrap(),
+                value(None, EntryValue::Number(Decimal::new(1, 0)), None),
+            ),
+            (
+                "129-129:199.130.3".parse().unwrap(),
+                value(None, EntryValue::Bytes(b"HLY".to_vec()), None),
+            ),
+        ]);
+
+        MeterReading {
+            server_id: Some(ServerId::new(&SERVER_ID)),
+            meter_time: Some(1234567),
+            values,
+        }
+    }
+
+    /// The readings of an SML file of `testdata/sml`, see `gen.py` there.
+    async fn fixture(name: &str) -> Vec<MeterReading> {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/sml")
+            .join(name);
+        let bytes = std::io::Cursor::new(std::fs::read(path).unwrap());
+        sml_message_stream(bytes, Arc::new(RegisterTable::default()))
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn parse_multi_message_file() {
+        let readings = fixture("multi_message.bin").await;
+        assert_eq!(readings.len(), 1);
+
+        let reading = &readings[0];
+        assert_eq!(reading.server_id, Some(ServerId::new(&SERVER_ID)));
+        assert_eq!(reading.meter_time, Some(1234567));
+        assert_eq!(
+            reading.decimal(&OBIS_TOTAL_INBOUND_COUNT),
+            Some(Decimal::new(6074471, -1))
+        );
+        assert_eq!(
+            reading.unit(&OBIS_TOTAL_INBOUND_COUNT),
+            Some(Unit::WattHour)
+        );
+        assert_eq!(
+            reading.decimal(&OBIS_CURRENT_NET_POWER),
+            Some(Decimal::new(4215, -1))
+        );
+        assert_eq!(reading.unit(&OBIS_CURRENT_NET_POWER), Some(Unit::Watt));
+        assert_eq!(
+            reading
+                .get(&"129-129:199.130.3".parse().unwrap())
+                .map(|value| &value.value),
+            Some(&EntryValue::Bytes(b"HLY".to_vec()))
+        );
+        assert_eq!(reading.values.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn parse_skips_other_server_ids() {
+        let readings = fixture("other_server.bin").await;
+        assert_eq!(readings.len(), 1);
+
+        let reading = &readings[0];
+        assert_eq!(reading.server_id, Some(ServerId::new(&SERVER_ID)));
+        assert!(reading.get(&OBIS_TOTAL_INBOUND_COUNT).is_some());
+        assert!(reading.get(&OBIS_CURRENT_NET_POWER).is_none());
+        assert_eq!(reading.values.len(), 2);
+    }
+
+    fn entry(obj_name: &[u8], unit: Option<u8>) -> ListEntry<'_> {
+        ListEntry {
+            obj_name,
+            status: None,
+            val_time: None,
+            unit,
+            scaler: Some(-1),
+            value: Value::I32(4215),
+            value_signature: None,
+        }
+    }
+
+    #[test]
+    fn parse_entry_units() {
+        let registers = RegisterTable::default();
+        let power = [1, 0, 16, 7, 0, 255];
+
+        let (_, value) = parse_entry(&entry(&power, Some(27)), &registers).unwrap();
+        assert_eq!(value.name.as_deref(), Some("power"));
+        assert_eq!(value.unit, Some(Unit::Watt));
+
+        // Kept without unit, dropped with another one.
+        let (_, value) = parse_entry(&entry(&power, None), &registers).unwrap();
+        assert_eq!(value.unit, None);
+        assert!(parse_entry(&entry(&power, Some(30)), &registers).is_none());
+        assert!(parse_entry(&entry(&power, Some(255)), &registers).is_none());
+
+        // Entries which aren't in the table accept any unit.
+        let unmapped = [1, 0, 96, 50, 1, 255];
+        let (_, value) = parse_entry(&entry(&unmapped, Some(30)), &registers).unwrap();
+        assert_eq!(value.name, None);
+        assert_eq!(value.unit, Some(Unit::WattHour));
+    }
+
+    /// `reading` of the meter `grid` received at 2024-05-01 12:00 UTC.
+    pub fn received(reading: MeterReading) -> ReceivedReading {
+        ReceivedReading {
+            meter: "grid".to_string(),
+            reading,
+            received_at: Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap(),
         }
     }
-    Ok(())
 }
@@ -0,0 +1,255 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Error;
+use rumqttc::{v5::{self,
+                   mqttbytes::{v5::{LastWill as LastWillV5, PublishProperties},
+                               QoS as QoSV5}},
+              AsyncClient,
+              ConnectionError,
+              Event,
+              EventLoop,
+              Incoming,
+              LastWill,
+              Outgoing,
+              QoS};
+
+use crate::{config::MqttConfig, mqtt::ProtocolVersion};
+
+/// Client of the protocol version of the broker.
+#[derive(Clone)]
+pub enum Client {
+    V3(AsyncClient),
+    V5(v5::AsyncClient),
+}
+
+/// Connection of a `Client`, driven by polling it.
+pub enum Connection {
+    V3(Box<EventLoop>),
+    V5(Box<v5::EventLoop>),
+}
+
+/// Properties of a message, only sent with MQTT 5.
+#[derive(Debug, Default)]
+pub struct MessageProperties {
+    /// Seconds after which the broker drops the message.
+    pub message_expiry: Option<u32>,
+    pub user:           Vec<(String, String)>,
+    /// Whether the topic is replaced by an alias, for the topics published
+    /// with every reading.
+    pub topic_alias:    bool,
+}
+
+pub enum ConnectionEvent {
+    /// The broker accepted the connection and allows up to
+    /// `topic_alias_max` topic aliases.
+    Connected {
+        topic_alias_max: u16,
+    },
+    /// A message was written to the connection.
+    Published,
+    Other,
+}
+
+pub enum ConnectionFailure {
+    /// The broker refused the connection with the reason code.
+    Refused(String),
+    Failed(String),
+}
+
+/// Topic aliases of MQTT 5, assigned to topics in the order they are
+/// published, up to the maximum of the broker.
+///
+/// An alias is only valid on the connection its topic was sent on, so a
+/// topic is sent with its alias once per connection and without afterwards.
+#[derive(Debug, Default)]
+pub struct TopicAliases {
+    /// Maximum of the broker, `0` before the first connect or if disabled.
+    max:         u16,
+    by_topic:    HashMap<String, u16>,
+    /// Aliases whose topic was sent on the current connection.
+    established: HashSet<u16>,
+}
+
+impl TopicAliases {
+    /// Forgets the aliases sent on the previous connection, `max` is the
+    /// maximum of the new one.
+    pub fn reset(&mut self, max: u16) {
+        self.max = max;
+        self.by_topic.retain(|_, alias| *alias <= max);
+        self.established.clear();
+    }
+
+    /// Alias of `topic`, assigned if there is one left, and whether it was
+    /// already sent with its topic.
+    fn get(&mut self, topic: &str) -> Option<(u16, bool)> {
+        let alias = match self.by_topic.get(topic) {
+            Some(alias) => *alias,
+            None => {
+                let alias = self.by_topic.len() as u16 + 1;
+                if alias > self.max {
+                    return None;
+                }
+                self.by_topic.insert(topic.to_string(), alias);
+                alias
+            },
+        };
+        Some((alias, self.established.contains(&alias)))
+    }
+
+    fn topic(&self, alias: u16) -> Option<&str> {
+        self.by_topic
+            .iter()
+            .find(|(_, other)| **other == alias)
+            .map(|(topic, _)| topic.as_str())
+    }
+}
+
+/// Creates the client of the configured protocol version with the retained
+/// Last Will `will_payload` on `will_topic`.
+pub fn new(
+    config: &MqttConfig,
+    will_topic: String,
+    will_payload: &str,
+    queue_size: usize,
+) -> Result<(Client, Connection), Error> {
+    match config.protocol_version {
+        ProtocolVersion::V3_1_1 => {
+            let mut options = config.options()?;
+            options.set_last_will(LastWill::new(will_topic, will_payload, config.qos(), true));
+            let (client, eventloop) = AsyncClient::new(options, queue_size);
+            Ok((Client::V3(client), Connection::V3(Box::new(eventloop))))
+        },
+        ProtocolVersion::V5 => {
+            let mut options = config.options_v5()?;
+            options.set_last_will(LastWillV5::new(
+                will_topic,
+                will_payload,
+                qos_v5(config.qos()),
+                true,
+                None,
+            ));
+            let (client, eventloop) = v5::AsyncClient::new(options, queue_size);
+            Ok((Client::V5(client), Connection::V5(Box::new(eventloop))))
+        },
+    }
+}
+
+impl Client {
+    /// Queues a message without waiting, fails if the queue is full.
+    pub fn try_publish(
+        &self,
+        topic: String,
+        qos: QoS,
+        retain: bool,
+        payload: impl Into<Vec<u8>>,
+        properties: MessageProperties,
+        aliases: &mut TopicAliases,
+    ) -> Result<(), Error> {
+        let payload = payload.into();
+        match self {
+            Client::V3(client) => client.try_publish(topic, qos, retain, payload)?,
+            Client::V5(client) => {
+                let alias = if properties.topic_alias {
+                    aliases.get(&topic)
+                } else {
+                    None
+                };
+                let properties = PublishProperties {
+                    message_expiry_interval: properties.message_expiry,
+                    topic_alias: alias.map(|(alias, _)| alias),
+                    user_properties: properties.user,
+                    ..PublishProperties::default()
+                };
+                let topic = match alias {
+                    Some((_, true)) => String::new(),
+                    _ => topic,
+                };
+                client.try_publish_with_properties(
+                    topic,
+                    qos_v5(qos),
+                    retain,
+                    payload,
+                    properties,
+                )?;
+
+                if let Some((alias, false)) = alias {
+                    aliases.established.insert(alias);
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+impl Connection {
+    /// Drives the connection until the next event, reconnects on the next
+    /// poll after a failure.
+    pub async fn poll(&mut self) -> Result<ConnectionEvent, ConnectionFailure> {
+        match self {
+            Connection::V3(eventloop) => {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Incoming::ConnAck(_))) => {
+                        Ok(ConnectionEvent::Connected { topic_alias_max: 0 })
+                    },
+                    Ok(Event::Outgoing(Outgoing::Publish(_))) => Ok(ConnectionEvent::Published),
+                    Ok(_) => Ok(ConnectionEvent::Other),
+                    Err(ConnectionError::ConnectionRefused(code)) => {
+                        Err(ConnectionFailure::Refused(format!("{code:?}")))
+                    },
+                    Err(e) => Err(ConnectionFailure::Failed(e.to_string())),
+                }
+            },
+            Connection::V5(eventloop) => {
+                match eventloop.poll().await {
+                    Ok(v5::Event::Incoming(v5::Incoming::ConnAck(connack))) => {
+                        let topic_alias_max = connack
+                            .properties
+                            .and_then(|properties| properties.topic_alias_max)
+                            .unwrap_or(0);
+                        Ok(ConnectionEvent::Connected { topic_alias_max })
+                    },
+                    Ok(v5::Event::Outgoing(Outgoing::Publish(_))) => Ok(ConnectionEvent::Published),
+                    Ok(_) => Ok(ConnectionEvent::Other),
+                    Err(v5::ConnectionError::ConnectionRefused(code)) => {
+                        Err(ConnectionFailure::Refused(format!("{code:?}")))
+                    },
+                    Err(e) => Err(ConnectionFailure::Failed(e.to_string())),
+                }
+            },
+        }
+    }
+
+    /// Puts the topics back into the messages queued with an alias before
+    /// the connection dropped, which are sent again on the new connection.
+    pub fn restore_topics(&mut self, aliases: &TopicAliases) {
+        let Connection::V5(eventloop) = self else {
+            return;
+        };
+
+        for request in &mut eventloop.pending {
+            let v5::Request::Publish(publish) = request else {
+                continue;
+            };
+            let Some(alias) = publish
+                .properties
+                .as_mut()
+                .and_then(|properties| properties.topic_alias.take())
+            else {
+                continue;
+            };
+            if publish.topic.is_empty() {
+                if let Some(topic) = aliases.topic(alias) {
+                    publish.topic = topic.to_string().into();
+                }
+            }
+        }
+    }
+}
+
+fn qos_v5(qos: QoS) -> QoSV5 {
+    match qos {
+        QoS::AtMostOnce => QoSV5::AtMostOnce,
+        QoS::AtLeastOnce => QoSV5::AtLeastOnce,
+        QoS::ExactlyOnce => QoSV5::ExactlyOnce,
+    }
+}
@@ -0,0 +1,253 @@
+use serde_json::{json, Value};
+
+use crate::{config::MqttConfig,
+            meter_reading::{MeterReading, ObisValue},
+            obis_code::ObisCode,
+            unit::Unit};
+
+/// A sensor announced to Home Assistant.
+pub struct Sensor {
+    /// Field of the sensor, unique per meter.
+    pub field:   String,
+    /// Topic of the discovery config,
+    /// `<discovery prefix>/sensor/<node>/<field>/config`.
+    pub topic:   String,
+    pub payload: String,
+}
+
+/// The Home Assistant sensors of the numeric values of `reading`.
+///
+/// The sensors of a meter are grouped by the node id, its server id or, for
+/// meters which send none, the MQTT client id.
+pub fn sensors(meter: &str, reading: &MeterReading, config: &MqttConfig) -> Vec<Sensor> {
+    let node = match &reading.server_id {
+        Some(server_id) => server_id.slug(),
+        None => sanitize(&config.client_id),
+    };
+
+    let mut device = json!({
+        "identifiers": [format!("power-meter_{node}")],
+        "name": meter,
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+    if let Some(server_id) = &reading.server_id {
+        device["serial_number"] = server_id.to_string().into();
+        if let Some(manufacturer) = server_id.manufacturer() {
+            device["manufacturer"] = manufacturer.into();
+        }
+    }
+
+    reading
+        .values
+        .iter()
+        .filter(|(_, value)| value.decimal().is_some() && config.publishes(value))
+        .map(|(obis_code, value)| {
+            let subtopic = super::subtopic(obis_code, value);
+            let field = field(obis_code, value);
+            let payload = sensor_config(&node, &field, &subtopic, value, &device, config);
+
+            Sensor {
+                topic: format!("{}/sensor/{node}/{field}/config", config.discovery.prefix),
+                payload: payload.to_string(),
+                field,
+            }
+        })
+        .collect()
+}
+
+/// The config of a sensor, which reads the raw value of its subtopic or, if
+/// only JSON is published, its value in `<prefix>/state`.
+fn sensor_config(
+    node: &str,
+    field: &str,
+    subtopic: &str,
+    value: &ObisValue,
+    device: &Value,
+    config: &MqttConfig,
+) -> Value {
+    let mut sensor = json!({
+        "name": field,
+        "unique_id": format!("{node}_{field}"),
+        "object_id": format!("{node}_{field}"),
+        "state_topic": config.topic(subtopic),
+        "availability_topic": config.topic("status"),
+        "payload_available": super::Status::Online.payload(),
+        "payload_not_available": super::Status::Offline.payload(),
+        "state_class": "measurement",
+        "device": device,
+    });
+
+    if !config.payload.raw() {
+        sensor["state_topic"] = config.topic("state").into();
+        sensor["value_template"] =
+            format!("{{{{ value_json['values']['{subtopic}']['value'] }}}}").into();
+    }
+
+    if let Some(unit) = &value.unit {
+        sensor["unit_of_measurement"] = unit.as_str().into();
+        let (device_class, state_class) = match unit {
+            Unit::Watt => (Some("power"), "measurement"),
+            Unit::WattHour => (Some("energy"), "total_increasing"),
+            Unit::Volt => (Some("voltage"), "measurement"),
+            Unit::Ampere => (Some("current"), "measurement"),
+            Unit::Hertz => (Some("frequency"), "measurement"),
+            Unit::Degree => (None, "measurement"),
+        };
+        if let Some(device_class) = device_class {
+            sensor["device_class"] = device_class.into();
+        }
+        sensor["state_class"] = state_class.into();
+    }
+
+    sensor
+}
+
+/// Field of a value in the discovery topic, its subtopic with the
+/// characters which aren't allowed replaced.
+pub fn field(obis_code: &ObisCode, value: &ObisValue) -> String {
+    sanitize(&super::subtopic(obis_code, value))
+}
+
+/// Replaces the characters which aren't allowed in the ids of a discovery
+/// topic, e.g. of `obis/1-0:96.50.1`.
+fn sanitize(id: &str) -> String {
+    id.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{meter_reading::tests::reading, mqtt::PayloadFormat};
+
+    fn payloads(config: &MqttConfig) -> BTreeMap<String, (String, Value)> {
+        sensors("grid", &reading(), config)
+            .into_iter()
+            .map(|sensor| {
+                let payload = serde_json::from_str(&sensor.payload).unwrap();
+                (sensor.field, (sensor.topic, payload))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn raw_sensors() {
+        let sensors = payloads(&MqttConfig::default());
+        assert_eq!(sensors.keys().collect::<Vec<_>>(), [
+            "energy_import",
+            "power"
+        ]);
+
+        let (topic, energy) = &sensors["energy_import"];
+        assert_eq!(
+            topic,
+            "homeassistant/sensor/1-HLY03-0207-2343/energy_import/config"
+        );
+        assert_eq!(
+            energy,
+            &json!({
+                "name": "energy_import",
+                "unique_id": "1-HLY03-0207-2343_energy_import",
+                "object_id": "1-HLY03-0207-2343_energy_import",
+                "state_topic": "power-meter/energy_import",
+                "availability_topic": "power-meter/status",
+                "payload_available": "online",
+                "payload_not_available": "offline",
+                "unit_of_measurement": "Wh",
+                "device_class": "energy",
+                "state_class": "total_increasing",
+                "device": {
+                    "identifiers": ["power-meter_1-HLY03-0207-2343"],
+                    "name": "grid",
+                    "sw_version": env!("CARGO_PKG_VERSION"),
+                    "serial_number": "1 HLY03 0207 2343",
+                    "manufacturer": "HLY",
+                },
+            })
+        );
+
+        let (_, power) = &sensors["power"];
+        assert_eq!(power["unit_of_measurement"], "W");
+        assert_eq!(power["device_class"], "power");
+        assert_eq!(power["state_class"], "measurement");
+    }
+
+    #[test]
+    fn json_sensors() {
+        let config = MqttConfig {
+            payload: PayloadFormat::Json,
+            ..MqttConfig::default()
+        };
+        let (_, power) = &payloads(&config)["power"];
+        assert_eq!(power["state_topic"], "power-meter/state");
+        assert_eq!(
+            power["value_template"],
+            "{{ value_json['values']['power']['value'] }}"
+        );
+
+        let config = MqttConfig {
+            payload: PayloadFormat::Both,
+            ..MqttConfig::default()
+        };
+        let (_, power) = &payloads(&config)["power"];
+        assert_eq!(power["state_topic"], "power-meter/power");
+        assert!(power.get("value_template").is_none());
+    }
+
+    #[test]
+    fn unmapped_sensors() {
+        let config = MqttConfig {
+            publish_unmapped: true,
+            ..MqttConfig::default()
+        };
+        let sensors = payloads(&config);
+        assert_eq!(sensors.keys().collect::<Vec<_>>(), [
+            "energy_import",
+            "obis_1-0_96_50_1",
+            "power"
+        ]);
+
+        let (topic, unmapped) = &sensors["obis_1-0_96_50_1"];
+        assert_eq!(
+            topic,
+            "homeassistant/sensor/1-HLY03-0207-2343/obis_1-0_96_50_1/config"
+        );
+        assert_eq!(unmapped["state_topic"], "power-meter/obis/1-0:96.50.1");
+        assert_eq!(unmapped["state_class"], "measurement");
+        assert!(unmapped.get("unit_of_measurement").is_none());
+        assert!(unmapped.get("device_class").is_none());
+    }
+
+    #[test]
+    fn node_without_server_id() {
+        let reading = MeterReading {
+            server_id: None,
+            ..reading()
+        };
+        let config = MqttConfig {
+            client_id: "power meter".to_string(),
+            ..MqttConfig::default()
+        };
+        let sensors = sensors("grid", &reading, &config);
+        assert_eq!(
+            sensors[0].topic,
+            "homeassistant/sensor/power_meter/energy_import/config"
+        );
+
+        let payload: Value = serde_json::from_str(&sensors[0].payload).unwrap();
+        assert_eq!(
+            payload["device"]["identifiers"],
+            json!(["power-meter_power_meter"])
+        );
+        assert!(payload["device"].get("serial_number").is_none());
+    }
+}
@@ -0,0 +1,510 @@
+mod client;
+mod discovery;
+mod state;
+mod throttle;
+pub mod tls;
+
+use std::{collections::HashSet,
+          sync::{atomic::{AtomicBool, AtomicU64, Ordering},
+                 Arc,
+                 Mutex},
+          time::Instant};
+
+use anyhow::{anyhow, bail, Context, Error};
+use chrono::Utc;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+pub use self::throttle::PublishPolicy;
+use self::{client::{Client,
+                    Connection,
+                    ConnectionEvent,
+                    ConnectionFailure,
+                    MessageProperties,
+                    TopicAliases},
+           throttle::Throttle};
+use crate::{config::MqttConfig,
+            meter_reading::{MeterReading, ObisValue, ReceivedReading},
+            obis_code::ObisCode,
+            register::RegisterTable,
+            unit::Unit};
+
+/// Number of entries which aren't in the register table a reading is
+/// expected to have at most, e.g. the server id and the manufacturer.
+const MAX_UNMAPPED_VALUES: usize = 32;
+
+/// Availability of a meter as published on `<prefix>/status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Online,
+    Offline,
+}
+
+impl Status {
+    fn payload(self) -> &'static str {
+        match self {
+            Status::Online => "online",
+            Status::Offline => "offline",
+        }
+    }
+}
+
+/// Layout of the published readings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadFormat {
+    /// One raw value per subtopic, e.g. `<prefix>/power`
+    Raw,
+    /// One JSON document per reading on `<prefix>/state`
+    Json,
+    /// Both layouts
+    Both,
+}
+
+impl PayloadFormat {
+    pub fn raw(self) -> bool { self != PayloadFormat::Json }
+
+    pub fn json(self) -> bool { self != PayloadFormat::Raw }
+}
+
+/// Version of the MQTT protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+pub enum ProtocolVersion {
+    #[serde(rename = "3.1.1")]
+    #[value(name = "3.1.1")]
+    V3_1_1,
+    /// With message expiry, user properties and topic aliases
+    #[serde(rename = "5")]
+    #[value(name = "5")]
+    V5,
+}
+
+/// Connection of a meter to the broker and the outcome of its messages,
+/// shared with the HTTP server.
+#[derive(Debug, Default)]
+pub struct MqttState {
+    connected:   AtomicBool,
+    connects:    AtomicU64,
+    disconnects: AtomicU64,
+    /// Messages written to the connection.
+    published:   AtomicU64,
+    /// Messages dropped as the broker wasn't connected or the queue was full.
+    failed:      AtomicU64,
+    last_error:  Mutex<Option<LastError>>,
+    /// Availability last published, restored after a reconnect.
+    status:      Mutex<Option<Status>>,
+    /// Fields announced to Home Assistant since the last connect.
+    announced:   Mutex<HashSet<String>>,
+    aliases:     Mutex<TopicAliases>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct LastError {
+    message: String,
+    at:      String,
+}
+
+/// Snapshot of an `MqttState`.
+#[derive(Debug, Serialize)]
+pub struct MqttHealth {
+    pub connected: bool,
+    connects:      u64,
+    disconnects:   u64,
+    published:     u64,
+    failed:        u64,
+    last_error:    Option<LastError>,
+}
+
+impl MqttState {
+    pub fn health(&self) -> MqttHealth {
+        MqttHealth {
+            connected:   self.connected.load(Ordering::Relaxed),
+            connects:    self.connects.load(Ordering::Relaxed),
+            disconnects: self.disconnects.load(Ordering::Relaxed),
+            published:   self.published.load(Ordering::Relaxed),
+            failed:      self.failed.load(Ordering::Relaxed),
+            last_error:  self.last_error.lock().unwrap().clone(),
+        }
+    }
+
+    fn set_error(&self, message: String) {
+        *self.last_error.lock().unwrap() = Some(LastError {
+            message,
+            at: Utc::now().to_rfc3339(),
+        });
+    }
+}
+
+/// Publishes the readings of a meter.
+///
+/// Publishing never waits for the broker, messages are dropped and counted
+/// as failed while it isn't connected, so a broker which is down doesn't
+/// hold up the meter.
+pub struct Publisher {
+    meter:          String,
+    client:         Client,
+    config:         MqttConfig,
+    state:          Arc<MqttState>,
+    /// Values last published on their subtopics.
+    raw_throttle:   Throttle,
+    /// Values last published in the document on `<prefix>/state`.
+    state_throttle: Throttle,
+}
+
+impl Publisher {
+    /// Connects to the broker with the Last Will `offline` on
+    /// `<prefix>/status`.
+    ///
+    /// The connection is driven by a background task, which reconnects with
+    /// exponential backoff.
+    pub fn connect(
+        meter: &str,
+        config: MqttConfig,
+        state: Arc<MqttState>,
+        registers: &RegisterTable,
+    ) -> Result<Self, Error> {
+        // Last Will: broker marks us offline if the connection drops, so evcc
+        // sees a stale meter instead of a silently frozen last value.
+        let (client, connection) = client::new(
+            &config,
+            config.topic("status"),
+            Status::Offline.payload(),
+            queue_size(&config, registers),
+        )?;
+        tokio::spawn(drive(
+            connection,
+            client.clone(),
+            config.clone(),
+            state.clone(),
+        ));
+
+        Ok(Publisher {
+            meter: meter.to_string(),
+            client,
+            config,
+            state,
+            raw_throttle: Throttle::default(),
+            state_throttle: Throttle::default(),
+        })
+    }
+
+    /// Publishes the availability of the meter (retained) if it changed.
+    pub fn set_status(&self, status: Status) {
+        {
+            let mut current = self.state.status.lock().unwrap();
+            if *current == Some(status) {
+                return;
+            }
+            *current = Some(status);
+        }
+
+        // Published after the next connect if the broker is down.
+        let _ = self.publish(
+            self.config.topic("status"),
+            true,
+            status.payload(),
+            MessageProperties::default(),
+        );
+    }
+
+    /// Publish every reading as **one raw numeric value per subtopic**,
+    /// retained.
+    ///
+    /// This is the layout evcc's `mqtt` plugin consumes directly (one topic =
+    /// one value, no JSON/jq), e.g. the grid meter reads `<prefix>/power`:
+    ///   - `<prefix>/power`         momentary net power in W (+ import / −
+    ///     export, OBIS 16.7.0)
+    ///   - `<prefix>/energy_import` total drawn from grid in Wh (OBIS 1.8.0)
+    ///   - `<prefix>/energy_export` total fed into grid in Wh   (OBIS 2.8.0)
+    ///   - `<prefix>/l1` `/l2` `/l3` per-phase power in W
+    ///
+    /// Every other register of the register table is published the same way
+    /// under its name, e.g. `<prefix>/voltage_l1`. With
+    /// `mqtt.publish_unmapped` the entries which aren't in the table are
+    /// published under their OBIS code too, e.g. `<prefix>/obis/1-0:96.50.1`.
+    ///
+    /// Retained by default (`mqtt.retain`) so a reconnecting subscriber (evcc,
+    /// Grafana) gets the last value immediately instead of waiting for the
+    /// next SML telegram.
+    ///
+    /// With `mqtt.payload = "json"` the reading is published as one JSON
+    /// document on `<prefix>/state` instead (see [`state::payload`]), with
+    /// `"both"` in both layouts.
+    ///
+    /// With `mqtt.publish` a value is only published on a change or after an
+    /// interval.
+    ///
+    /// With MQTT 5 the messages carry the unit, the time of the meter and its
+    /// server id as user properties, and expire after
+    /// `mqtt.publish.message_expiry`.
+    ///
+    /// With `mqtt.discovery` the values are announced to Home Assistant
+    /// first.
+    pub fn publish_data(&mut self, received: &ReceivedReading) -> Result<(), Error> {
+        let reading = &received.reading;
+        if self.config.discovery.enabled {
+            self.announce(reading);
+        }
+
+        let now = Instant::now();
+        let fields: Vec<_> = reading
+            .values
+            .iter()
+            .filter(|(_, value)| self.config.publishes(value))
+            .map(|(obis_code, value)| {
+                let field = subtopic(obis_code, value);
+                let policy = self.config.publish.policy(&field);
+                (field, value, policy)
+            })
+            .collect();
+
+        let mut messages = 0;
+        let mut failed = 0;
+        if self.config.payload.raw() {
+            for (field, value, policy) in &fields {
+                if !self.raw_throttle.is_due(field, &value.value, policy, now) {
+                    continue;
+                }
+                messages += 1;
+                let topic = self.config.topic(field);
+                let properties = properties(reading, value.unit.as_ref(), policy.message_expiry);
+                if self
+                    .publish(
+                        topic,
+                        self.config.retain,
+                        value.value.to_string(),
+                        properties,
+                    )
+                    .is_ok()
+                {
+                    self.raw_throttle.record(field, &value.value, now);
+                } else {
+                    failed += 1;
+                }
+            }
+        }
+        // The document is published if any of its values is due.
+        let state_due = fields.iter().any(|(field, value, policy)| {
+            self.state_throttle.is_due(field, &value.value, policy, now)
+        });
+        if self.config.payload.json() && state_due {
+            let payload = state::payload(&self.meter, received, &self.config);
+            let message_expiry = self.config.publish.default_policy().message_expiry;
+            messages += 1;
+            if self
+                .publish(
+                    self.config.topic("state"),
+                    self.config.retain,
+                    payload,
+                    properties(reading, None, message_expiry),
+                )
+                .is_ok()
+            {
+                for (field, value, _) in &fields {
+                    self.state_throttle.record(field, &value.value, now);
+                }
+            } else {
+                failed += 1;
+            }
+        }
+
+        if failed > 0 {
+            bail!("{failed} of {messages} messages were not published");
+        }
+        Ok(())
+    }
+
+    /// Publishes the retained discovery configs of the fields which weren't
+    /// announced since the last connect.
+    fn announce(&self, reading: &MeterReading) {
+        let mut announced = self.state.announced.lock().unwrap();
+        let pending = reading.values.iter().any(|(obis_code, value)| {
+            value.decimal().is_some()
+                && self.config.publishes(value)
+                && !announced.contains(&discovery::field(obis_code, value))
+        });
+        if !pending {
+            return;
+        }
+
+        for sensor in discovery::sensors(&self.meter, reading, &self.config) {
+            if announced.contains(&sensor.field) {
+                continue;
+            }
+            if self
+                .publish(
+                    sensor.topic,
+                    true,
+                    sensor.payload,
+                    MessageProperties::default(),
+                )
+                .is_ok()
+            {
+                announced.insert(sensor.field);
+            }
+        }
+    }
+
+    fn publish(
+        &self,
+        topic: String,
+        retain: bool,
+        payload: impl Into<Vec<u8>>,
+        properties: MessageProperties,
+    ) -> Result<(), Error> {
+        let result = if self.state.connected.load(Ordering::Relaxed) {
+            self.client
+                .try_publish(
+                    topic,
+                    self.config.qos(),
+                    retain,
+                    payload,
+                    properties,
+                    &mut self.state.aliases.lock().unwrap(),
+                )
+                .context("The queue of the connection is full")
+        } else {
+            Err(anyhow!("Not connected to the broker"))
+        };
+
+        if result.is_err() {
+            self.state.failed.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+}
+
+/// Polls the connection, which reconnects on the next poll after an error.
+async fn drive(
+    mut connection: Connection,
+    client: Client,
+    config: MqttConfig,
+    state: Arc<MqttState>,
+) {
+    let broker = format!("{}:{}", config.host, config.port);
+    let mut delay = config.reconnect_delay();
+
+    loop {
+        match connection.poll().await {
+            Ok(ConnectionEvent::Connected { topic_alias_max }) => {
+                log::info!("Connected to MQTT broker {broker} as {}", config.client_id);
+                state.connected.store(true, Ordering::Relaxed);
+                state.connects.fetch_add(1, Ordering::Relaxed);
+                // The broker may have lost the retained configs.
+                state.announced.lock().unwrap().clear();
+                delay = config.reconnect_delay();
+
+                let mut aliases = state.aliases.lock().unwrap();
+                connection.restore_topics(&aliases);
+                aliases.reset(if config.topic_aliases {
+                    topic_alias_max
+                } else {
+                    0
+                });
+
+                // The broker published the Last Will if the connection
+                // dropped, so the availability is published again.
+                let status = *state.status.lock().unwrap();
+                if let Some(status) = status {
+                    let _ = client.try_publish(
+                        config.topic("status"),
+                        config.qos(),
+                        true,
+                        status.payload(),
+                        MessageProperties::default(),
+                        &mut aliases,
+                    );
+                }
+            },
+            Ok(ConnectionEvent::Published) => {
+                state.published.fetch_add(1, Ordering::Relaxed);
+            },
+            Ok(ConnectionEvent::Other) => {},
+            Err(failure) => {
+                let message = match &failure {
+                    ConnectionFailure::Refused(code) => {
+                        format!("MQTT broker {broker} refused the connection: {code}")
+                    },
+                    ConnectionFailure::Failed(e) if state.connected.load(Ordering::Relaxed) => {
+                        format!("Lost connection to MQTT broker {broker}: {e}")
+                    },
+                    ConnectionFailure::Failed(e) => {
+                        format!("Failed to connect to MQTT broker {broker}: {e}")
+                    },
+                };
+                if matches!(failure, ConnectionFailure::Refused(_)) {
+                    log::error!("{message}, retrying in {}s", delay.as_secs());
+                } else {
+                    log::warn!("{message}, retrying in {}s", delay.as_secs());
+                }
+
+                if state.connected.swap(false, Ordering::Relaxed) {
+                    state.disconnects.fetch_add(1, Ordering::Relaxed);
+                }
+                state.set_error(message);
+
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(config.reconnect_max_delay());
+            },
+        }
+    }
+}
+
+/// Number of messages queued for the connection, publishing fails while the
+/// queue is full.
+///
+/// A reading is queued at once, before the connection sends any of it, so
+/// the queue holds every message of two readings including the discovery
+/// configs.
+fn queue_size(config: &MqttConfig, registers: &RegisterTable) -> usize {
+    let mut values = registers.registers().len();
+    if config.publish_unmapped {
+        values += MAX_UNMAPPED_VALUES;
+    }
+
+    // The status.
+    let mut messages = 1;
+    if config.payload.raw() {
+        messages += values;
+    }
+    if config.payload.json() {
+        messages += 1;
+    }
+    if config.discovery.enabled {
+        messages += values;
+    }
+    2 * messages
+}
+
+/// Properties of a message of `reading`, with the unit of its value.
+fn properties(
+    reading: &MeterReading,
+    unit: Option<&Unit>,
+    message_expiry: Option<u32>,
+) -> MessageProperties {
+    let mut user = Vec::new();
+    if let Some(unit) = unit {
+        user.push(("unit".to_string(), unit.as_str().to_string()));
+    }
+    if let Some(meter_time) = reading.meter_time {
+        user.push(("meter_time".to_string(), meter_time.to_string()));
+    }
+    if let Some(server_id) = &reading.server_id {
+        user.push(("server_id".to_string(), server_id.to_string()));
+    }
+
+    MessageProperties {
+        message_expiry,
+        user,
+        topic_alias: true,
+    }
+}
+
+/// Subtopic of a value below the prefix, its name or, for registers which
+/// aren't in the table, `obis/<code>`.
+fn subtopic(obis_code: &ObisCode, value: &ObisValue) -> String {
+    match &value.name {
+        Some(name) => name.clone(),
+        None => format!("obis/{obis_code}"),
+    }
+}
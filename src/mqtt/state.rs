@@ -0,0 +1,105 @@
+use serde_json::{json, Map};
+
+use crate::{config::MqttConfig, meter_reading::ReceivedReading, unit::Unit};
+
+/// Version of the JSON document on `<prefix>/state`, increased on every
+/// change which isn't backwards compatible.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// JSON document of a reading with every published value by its subtopic,
+/// e.g.
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "meter": "grid",
+///   "server_id": "1 HLY03 0207 2343",
+///   "meter_time": 1234567,
+///   "received_at": "2024-05-01T12:00:00.123+00:00",
+///   "values": {
+///     "power": { "obis": "1-0:16.7.0", "value": 421.5, "unit": "W" },
+///     "energy_import": { "obis": "1-0:1.8.0", "value": 607447.1, "unit": "Wh" }
+///   }
+/// }
+/// ```
+pub fn payload(meter: &str, received: &ReceivedReading, config: &MqttConfig) -> String {
+    let reading = &received.reading;
+    let values: Map<_, _> = reading
+        .values
+        .iter()
+        .filter(|(_, value)| config.publishes(value))
+        .map(|(obis_code, value)| {
+            let entry = json!({
+                "obis": obis_code,
+                "value": value.value,
+                "unit": value.unit.as_ref().map(Unit::as_str),
+            });
+            (super::subtopic(obis_code, value), entry)
+        })
+        .collect();
+
+    json!({
+        "version": SCHEMA_VERSION,
+        "meter": meter,
+        "server_id": reading.server_id,
+        "meter_time": reading.meter_time,
+        "received_at": received.received_at.to_rfc3339(),
+        "values": values,
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::meter_reading::{tests::{reading, received},
+                               MeterReading};
+
+    fn parse(payload: &str) -> Value { serde_json::from_str(payload).unwrap() }
+
+    #[test]
+    fn document() {
+        let payload = payload("grid", &received(reading()), &MqttConfig::default());
+
+        assert_eq!(
+            parse(&payload),
+            json!({
+                "version": SCHEMA_VERSION,
+                "meter": "grid",
+                "server_id": "1 HLY03 0207 2343",
+                "meter_time": 1234567,
+                "received_at": "2024-05-01T12:00:00+00:00",
+                "values": {
+                    "energy_import": { "obis": "1-0:1.8.0", "value": 607447.1, "unit": "Wh" },
+                    "power": { "obis": "1-0:16.7.0", "value": 421.5, "unit": "W" },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn unmapped_values() {
+        let config = MqttConfig {
+            publish_unmapped: true,
+            ..MqttConfig::default()
+        };
+        let reading = MeterReading {
+            server_id: None,
+            ..reading()
+        };
+        let payload = parse(&payload("grid", &received(reading), &config));
+
+        assert_eq!(payload["server_id"], Value::Null);
+        assert_eq!(
+            payload["values"]["obis/129-129:199.130.3"],
+            json!({ "obis": "129-129:199.130.3", "value": "484c59", "unit": null })
+        );
+        assert_eq!(
+            payload["values"]["obis/1-0:96.50.1"],
+            json!({ "obis": "1-0:96.50.1", "value": 1.0, "unit": null })
+        );
+        assert_eq!(payload["values"].as_object().unwrap().len(), 4);
+    }
+}
@@ -0,0 +1,261 @@
+use std::{collections::HashMap,
+          time::{Duration, Instant}};
+
+use crate::meter_reading::EntryValue;
+
+/// When and how the values of a field are published, see `PublishConfig`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PublishPolicy {
+    pub min_interval:     Duration,
+    pub max_interval:     Option<Duration>,
+    pub deadband:         f64,
+    pub deadband_percent: f64,
+    /// Seconds after which the broker drops a message.
+    pub message_expiry:   Option<u32>,
+}
+
+impl PublishPolicy {
+    /// Whether the change from `last` to `value` is published, any change
+    /// of a number which reaches one of the deadbands and any change of
+    /// other values. Without deadbands every value is published, even if it
+    /// didn't change.
+    fn is_change(&self, last: &EntryValue, value: &EntryValue) -> bool {
+        if self.deadband == 0.0 && self.deadband_percent == 0.0 {
+            return true;
+        }
+        let (EntryValue::Number(last), EntryValue::Number(value)) = (last, value) else {
+            return last != value;
+        };
+
+        let last = last.to_f64();
+        let change = (value.to_f64() - last).abs();
+        (self.deadband > 0.0 && change >= self.deadband)
+            || (self.deadband_percent > 0.0
+                && change > 0.0
+                && change >= last.abs() * self.deadband_percent / 100.0)
+    }
+}
+
+/// The values last published per field.
+#[derive(Default)]
+pub struct Throttle {
+    published: HashMap<String, Published>,
+}
+
+struct Published {
+    value: EntryValue,
+    at:    Instant,
+}
+
+impl Throttle {
+    /// Whether `value` of `field` is published at `now`: the first value,
+    /// once the maximum interval passed and on a change, but not before the
+    /// minimum interval passed.
+    pub fn is_due(
+        &self,
+        field: &str,
+        value: &EntryValue,
+        policy: &PublishPolicy,
+        now: Instant,
+    ) -> bool {
+        let Some(published) = self.published.get(field) else {
+            return true;
+        };
+
+        let elapsed = now.saturating_duration_since(published.at);
+        if elapsed < policy.min_interval {
+            return false;
+        }
+        policy
+            .max_interval
+            .is_some_and(|max_interval| elapsed >= max_interval)
+            || policy.is_change(&published.value, value)
+    }
+
+    /// Remembers that `value` of `field` was published at `now`.
+    pub fn record(&mut self, field: &str, value: &EntryValue, now: Instant) {
+        self.published.insert(field.to_string(), Published {
+            value: value.clone(),
+            at:    now,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decimal::Decimal;
+
+    const NONE: PublishPolicy = PublishPolicy {
+        min_interval:     Duration::ZERO,
+        max_interval:     None,
+        deadband:         0.0,
+        deadband_percent: 0.0,
+        message_expiry:   None,
+    };
+
+    fn number(value: i64) -> EntryValue { EntryValue::Number(Decimal::new(value, 0)) }
+
+    /// A throttle which published `value` of `power` at `start`.
+    fn throttle(value: i64, start: Instant) -> Throttle {
+        let mut throttle = Throttle::default();
+        throttle.record("power", &number(value), start);
+        throttle
+    }
+
+    #[test]
+    fn first_value() {
+        let policy = PublishPolicy {
+            min_interval: Duration::from_secs(60),
+            deadband: 10.0,
+            ..NONE
+        };
+        let now = Instant::now();
+        let throttle = throttle(100, now);
+
+        assert!(throttle.is_due("energy_import", &number(100), &policy, now));
+        assert!(!throttle.is_due("power", &number(100), &policy, now));
+    }
+
+    #[test]
+    fn without_deadband() {
+        let now = Instant::now();
+        let throttle = throttle(100, now);
+
+        assert!(throttle.is_due("power", &number(100), &NONE, now));
+        assert!(throttle.is_due("power", &number(101), &NONE, now));
+    }
+
+    #[test]
+    fn deadband() {
+        let policy = PublishPolicy {
+            deadband: 10.0,
+            ..NONE
+        };
+        let now = Instant::now();
+        let throttle = throttle(100, now);
+
+        assert!(!throttle.is_due("power", &number(100), &policy, now));
+        assert!(!throttle.is_due("power", &number(109), &policy, now));
+        assert!(!throttle.is_due("power", &number(91), &policy, now));
+        assert!(throttle.is_due("power", &number(110), &policy, now));
+        assert!(throttle.is_due("power", &number(90), &policy, now));
+    }
+
+    #[test]
+    fn deadband_percent() {
+        let policy = PublishPolicy {
+            deadband_percent: 5.0,
+            ..NONE
+        };
+        let now = Instant::now();
+        let throttle = throttle(-200, now);
+
+        assert!(!throttle.is_due("power", &number(-200), &policy, now));
+        assert!(!throttle.is_due("power", &number(-191), &policy, now));
+        assert!(throttle.is_due("power", &number(-190), &policy, now));
+        assert!(throttle.is_due("power", &number(-210), &policy, now));
+    }
+
+    #[test]
+    fn either_deadband() {
+        let policy = PublishPolicy {
+            deadband: 50.0,
+            deadband_percent: 1.0,
+            ..NONE
+        };
+        let now = Instant::now();
+
+        assert!(throttle(1000, now).is_due("power", &number(1010), &policy, now));
+        assert!(throttle(100_000, now).is_due("power", &number(100_050), &policy, now));
+        assert!(!throttle(100_000, now).is_due("power", &number(100_049), &policy, now));
+    }
+
+    #[test]
+    fn previous_zero() {
+        let policy = PublishPolicy {
+            deadband_percent: 5.0,
+            ..NONE
+        };
+        let now = Instant::now();
+        let throttle = throttle(0, now);
+
+        assert!(!throttle.is_due("power", &number(0), &policy, now));
+        assert!(throttle.is_due("power", &number(1), &policy, now));
+        assert!(throttle.is_due("power", &number(-1), &policy, now));
+
+        let policy = PublishPolicy {
+            deadband: 10.0,
+            ..policy
+        };
+        assert!(!throttle.is_due("power", &number(0), &policy, now));
+        assert!(throttle.is_due("power", &number(1), &policy, now));
+    }
+
+    #[test]
+    fn other_values() {
+        let policy = PublishPolicy {
+            deadband: 10.0,
+            ..NONE
+        };
+        let now = Instant::now();
+        let mut throttle = Throttle::default();
+        throttle.record("firmware", &EntryValue::Bytes(vec![1, 2]), now);
+
+        let same = EntryValue::Bytes(vec![1, 2]);
+        assert!(!throttle.is_due("firmware", &same, &policy, now));
+        let changed = EntryValue::Bytes(vec![1, 3]);
+        assert!(throttle.is_due("firmware", &changed, &policy, now));
+    }
+
+    #[test]
+    fn min_interval() {
+        let policy = PublishPolicy {
+            min_interval: Duration::from_secs(10),
+            deadband: 10.0,
+            ..NONE
+        };
+        let start = Instant::now();
+        let throttle = throttle(100, start);
+
+        let before = start + Duration::from_secs(9);
+        assert!(!throttle.is_due("power", &number(500), &policy, before));
+        let after = start + Duration::from_secs(10);
+        assert!(throttle.is_due("power", &number(500), &policy, after));
+        assert!(!throttle.is_due("power", &number(100), &policy, after));
+
+        let policy = PublishPolicy {
+            deadband: 0.0,
+            ..policy
+        };
+        assert!(!throttle.is_due("power", &number(100), &policy, before));
+        assert!(throttle.is_due("power", &number(100), &policy, after));
+    }
+
+    #[test]
+    fn max_interval() {
+        let policy = PublishPolicy {
+            min_interval: Duration::from_secs(10),
+            max_interval: Some(Duration::from_secs(60)),
+            deadband: 10.0,
+            ..NONE
+        };
+        let start = Instant::now();
+        let mut throttle = throttle(100, start);
+
+        let before = start + Duration::from_secs(59);
+        assert!(!throttle.is_due("power", &number(101), &policy, before));
+        let heartbeat = start + Duration::from_secs(60);
+        assert!(throttle.is_due("power", &number(101), &policy, heartbeat));
+
+        throttle.record("power", &number(101), heartbeat);
+        let next = heartbeat + Duration::from_secs(30);
+        assert!(!throttle.is_due("power", &number(101), &policy, next));
+        assert!(throttle.is_due(
+            "power",
+            &number(101),
+            &policy,
+            heartbeat + Duration::from_secs(60)
+        ));
+    }
+}
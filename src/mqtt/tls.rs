@@ -0,0 +1,366 @@
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+
+use anyhow::{bail, Context, Error};
+use rustls::{client::{danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+                      WebPkiServerVerifier},
+             pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+             ClientConfig,
+             DigitallySignedStruct,
+             RootCertStore,
+             SignatureScheme};
+
+use crate::config::TlsConfig;
+
+/// The TLS settings of the connection to the broker.
+///
+/// The certificate of the broker is verified against the CA bundle, or the
+/// certificates of the system if none is configured.
+pub fn client_config(tls: &TlsConfig) -> Result<Arc<ClientConfig>, Error> {
+    let mut roots = RootCertStore::empty();
+    match &tls.ca_file {
+        Some(ca_file) => {
+            for cert in read_certs(ca_file)? {
+                roots
+                    .add(cert)
+                    .with_context(|| format!("Invalid CA certificate in {}", ca_file.display()))?;
+            }
+        },
+        None => {
+            let certs = rustls_native_certs::load_native_certs()
+                .context("Failed to load the CA certificates of the system")?;
+            roots.add_parsable_certificates(certs);
+        },
+    }
+    let roots = Arc::new(roots);
+
+    let builder = match &tls.verify_name {
+        Some(verify_name) => {
+            let name = ServerName::try_from(verify_name.clone())
+                .with_context(|| format!("Invalid verify name \"{verify_name}\""))?;
+            let verifier = NameVerifier {
+                inner: WebPkiServerVerifier::builder(roots).build()?,
+                name,
+            };
+            ClientConfig::builder()
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(verifier))
+        },
+        None => ClientConfig::builder().with_root_certificates(roots),
+    };
+
+    let mut config = match (&tls.cert_file, &tls.key_file) {
+        (Some(cert_file), Some(key_file)) => {
+            let certs = read_certs(cert_file)?;
+            let key = read_key(key_file)?;
+            builder
+                .with_client_auth_cert(certs, key)
+                .context("Invalid client certificate or key")?
+        },
+        _ => builder.with_no_client_auth(),
+    };
+    config.alpn_protocols = tls
+        .alpn
+        .iter()
+        .map(|protocol| protocol.as_bytes().to_vec())
+        .collect();
+
+    Ok(Arc::new(config))
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    if certs.is_empty() {
+        bail!("No PEM certificate in {}", path.display());
+    }
+    Ok(certs)
+}
+
+fn read_key(path: &Path) -> Result<PrivateKeyDer<'static>, Error> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .with_context(|| format!("No PEM private key in {}", path.display()))
+}
+
+/// Verifies the certificate of the broker against the configured name
+/// instead of the host connected to, e.g. for a broker reached by its IP
+/// address. The name sent by SNI is still the host.
+#[derive(Debug)]
+struct NameVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    name:  ServerName<'static>,
+}
+
+impl ServerCertVerifier for NameVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.inner
+            .verify_server_cert(end_entity, intermediates, &self.name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{Read, Write},
+              net::TcpListener,
+              path::PathBuf,
+              thread::{self, JoinHandle},
+              time::Duration};
+
+    use rumqttc::{Event, EventLoop, Incoming};
+    use rustls::{server::WebPkiClientVerifier, ServerConfig, ServerConnection, StreamOwned};
+
+    use super::*;
+    use crate::config::MqttConfig;
+
+    /// A file of the throwaway PKI, see `testdata/tls/gen.sh`.
+    fn testdata(file: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/tls")
+            .join(file)
+    }
+
+    /// User name and password of a CONNECT packet.
+    type Credentials = Option<(String, String)>;
+
+    /// A broker on a free port, which accepts one TLS connection and answers
+    /// its CONNECT. The thread returns the credentials of the client, or
+    /// `None` if the connection failed.
+    fn broker(client_auth: bool) -> (u16, JoinHandle<Option<Credentials>>) {
+        let builder = ServerConfig::builder();
+        let builder = if client_auth {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(&testdata("ca.pem")).unwrap() {
+                roots.add(cert).unwrap();
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()
+                .unwrap();
+            builder.with_client_cert_verifier(verifier)
+        } else {
+            builder.with_no_client_auth()
+        };
+        let config = builder
+            .with_single_cert(
+                read_certs(&testdata("server.pem")).unwrap(),
+                read_key(&testdata("server.key")).unwrap(),
+            )
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let connection = ServerConnection::new(Arc::new(config)).unwrap();
+            let mut stream = StreamOwned::new(connection, socket);
+
+            let credentials = read_connect(&mut stream).ok()?;
+            stream.write_all(&[0x20, 0x02, 0x00, 0x00]).ok()?;
+            stream.flush().ok()?;
+            Some(credentials)
+        });
+        (port, handle)
+    }
+
+    /// Reads a CONNECT packet of MQTT 3.1.1.
+    fn read_connect(stream: &mut impl Read) -> std::io::Result<Credentials> {
+        let mut byte = [0];
+        stream.read_exact(&mut byte)?;
+        assert_eq!(byte[0], 0x10);
+        let mut length = 0;
+        for shift in (0..28).step_by(7) {
+            stream.read_exact(&mut byte)?;
+            length |= usize::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut packet = vec![0; length];
+        stream.read_exact(&mut packet)?;
+
+        // Protocol name, level, flags and keep alive.
+        let flags = packet[7];
+        let mut fields = &packet[10..];
+        let mut field = || {
+            let length = usize::from(u16::from_be_bytes([fields[0], fields[1]]));
+            let value = String::from_utf8_lossy(&fields[2..2 + length]).into_owned();
+            fields = &fields[2 + length..];
+            value
+        };
+        let _client_id = field();
+        if flags & 0x04 != 0 {
+            let _will_topic = field();
+            let _will_message = field();
+        }
+        if flags & 0x80 == 0 {
+            return Ok(None);
+        }
+        let username = field();
+        let password = if flags & 0x40 != 0 {
+            field()
+        } else {
+            String::new()
+        };
+        Ok(Some((username, password)))
+    }
+
+    /// Connects to the broker on `port`, until the CONNACK or an error.
+    async fn connect(config: MqttConfig, port: u16) -> Result<(), String> {
+        let config = MqttConfig {
+            port,
+            tls: TlsConfig {
+                enabled: true,
+                ..config.tls
+            },
+            ..config
+        };
+        let mut eventloop = EventLoop::new(config.options().unwrap(), 10);
+        let connack = async {
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Incoming::ConnAck(_))) => return Ok(()),
+                    Ok(_) => {},
+                    Err(err) => return Err(err.to_string()),
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), connack)
+            .await
+            .map_err(|_| "Timed out".to_string())?
+    }
+
+    fn tls(ca_file: &str) -> TlsConfig {
+        TlsConfig {
+            ca_file: Some(testdata(ca_file)),
+            ..TlsConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn ca_verification() {
+        let (port, handle) = broker(false);
+        let config = MqttConfig {
+            host: "localhost".to_string(),
+            tls: tls("ca.pem"),
+            ..MqttConfig::default()
+        };
+        connect(config, port).await.unwrap();
+        assert_eq!(handle.join().unwrap(), Some(None));
+    }
+
+    #[tokio::test]
+    async fn verify_name() {
+        let (port, handle) = broker(false);
+        let config = MqttConfig {
+            host: "127.0.0.1".to_string(),
+            tls: TlsConfig {
+                verify_name: Some("broker.test".to_string()),
+                ..tls("ca.pem")
+            },
+            ..MqttConfig::default()
+        };
+        connect(config, port).await.unwrap();
+        handle.join().unwrap().unwrap();
+
+        let (port, handle) = broker(false);
+        let config = MqttConfig {
+            host: "127.0.0.1".to_string(),
+            tls: tls("ca.pem"),
+            ..MqttConfig::default()
+        };
+        let err = connect(config, port).await.unwrap_err();
+        assert!(err.contains("NotValidForName"), "{err}");
+        assert_eq!(handle.join().unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn client_cert() {
+        let (port, handle) = broker(true);
+        let config = MqttConfig {
+            host: "localhost".to_string(),
+            tls: TlsConfig {
+                cert_file: Some(testdata("client.pem")),
+                key_file: Some(testdata("client.key")),
+                ..tls("ca.pem")
+            },
+            ..MqttConfig::default()
+        };
+        connect(config, port).await.unwrap();
+        handle.join().unwrap().unwrap();
+
+        let (port, handle) = broker(true);
+        let config = MqttConfig {
+            host: "localhost".to_string(),
+            tls: tls("ca.pem"),
+            ..MqttConfig::default()
+        };
+        let err = connect(config, port).await.unwrap_err();
+        assert!(err.contains("CertificateRequired"), "{err}");
+        assert_eq!(handle.join().unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn password_file() {
+        let (port, handle) = broker(false);
+        let config = MqttConfig {
+            host: "localhost".to_string(),
+            username: Some("meter".to_string()),
+            password: Some("ignored".to_string()),
+            password_file: Some(testdata("password")),
+            tls: tls("ca.pem"),
+            ..MqttConfig::default()
+        };
+        connect(config, port).await.unwrap();
+        assert_eq!(
+            handle.join().unwrap(),
+            Some(Some(("meter".to_string(), "secret".to_string())))
+        );
+    }
+
+    #[tokio::test]
+    async fn wrong_ca() {
+        let (port, handle) = broker(false);
+        let config = MqttConfig {
+            host: "localhost".to_string(),
+            tls: tls("other-ca.pem"),
+            ..MqttConfig::default()
+        };
+        let err = connect(config, port).await.unwrap_err();
+        assert!(err.contains("UnknownIssuer"), "{err}");
+        assert_eq!(handle.join().unwrap(), None);
+    }
+}
@@ -1,5 +1,6 @@
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
 
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use sml_rs::parser::OctetStr;
 
 /// A code as defined in [OBIS][obis]
@@ -70,6 +71,7 @@ impl ObisCode {
     /// Views this Obis code as a slice of bytes.
     // pub const fn as_bytes(&self) -> &[u8; 5] { &self.inner }
 
+    #[allow(clippy::empty_line_after_doc_comments)]
     const fn try_from_str(s: &str) -> Result<Self, ObisParseError> {
         const SEPARATORS: &[u8; 4] = b"-:..";
         let bytes = s.as_bytes();
@@ -128,7 +130,9 @@ pub enum ObisParseError {
     UnexpectedSeparator,
     /// Provided octet string has invalid length
     InvalidLength,
-    // / Provided octet string's last byte doesn't equal 255
+    /// Provided octet string's last byte doesn't equal 255 (not checked, as
+    /// meters send other values as well)
+    #[allow(dead_code)]
     InvalidLastByte,
 }
 
@@ -145,10 +149,10 @@ impl ObisParseError {
     }
 }
 
-impl core::convert::TryFrom<&str> for ObisCode {
-    type Error = ObisParseError;
+impl FromStr for ObisCode {
+    type Err = ObisParseError;
 
-    fn try_from(value: &str) -> Result<Self, Self::Error> { Self::try_from_str(value) }
+    fn from_str(value: &str) -> Result<Self, Self::Err> { Self::try_from_str(value) }
 }
 
 impl core::convert::TryFrom<OctetStr<'_>> for ObisCode {
@@ -166,3 +170,20 @@ impl core::convert::TryFrom<&[u8; 6]> for ObisCode {
         Self::try_from_octet_str(value.as_slice())
     }
 }
+
+impl<'de> Deserialize<'de> for ObisCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse::<ObisCode>().map_err(|e| {
+            de::Error::custom(format!(
+                "invalid OBIS code \"{s}\" ({e:?}), expected e.g. \"1-0:1.8.0\""
+            ))
+        })
+    }
+}
+
+impl Serialize for ObisCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
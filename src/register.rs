@@ -0,0 +1,157 @@
+use anyhow::{bail, Error};
+use serde::Deserialize;
+
+use crate::{obis_code::ObisCode, unit::Unit};
+
+/// A named register of the meter.
+///
+/// The `name` identifies the value everywhere else, e.g. as MQTT subtopic
+/// (`<prefix>/<name>`) and as key in the JSON API.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Register {
+    /// OBIS code of the register, e.g. `"1-0:1.8.0"`
+    pub obis: ObisCode,
+    pub name: String,
+    /// Unit the meter is expected to send, values with another unit are
+    /// dropped, values without one are kept. `None` accepts any unit.
+    #[serde(default)]
+    pub unit: Option<Unit>,
+}
+
+pub const OBIS_TOTAL_INBOUND_COUNT: ObisCode = ObisCode::from_octet_str(&[1, 0, 1, 8, 0, 255]);
+pub const OBIS_TOTAL_OUTBOUND_COUNT: ObisCode = ObisCode::from_octet_str(&[1, 0, 2, 8, 0, 255]);
+pub const OBIS_CURRENT_NET_POWER: ObisCode = ObisCode::from_octet_str(&[1, 0, 16, 7, 0, 255]);
+pub const OBIS_LINE_ONE: ObisCode = ObisCode::from_octet_str(&[1, 0, 36, 7, 0, 255]);
+pub const OBIS_LINE_TWO: ObisCode = ObisCode::from_octet_str(&[1, 0, 56, 7, 0, 255]);
+pub const OBIS_LINE_THREE: ObisCode = ObisCode::from_octet_str(&[1, 0, 76, 7, 0, 255]);
+
+struct DefaultRegister {
+    obis: ObisCode,
+    name: &'static str,
+    unit: Unit,
+}
+
+impl DefaultRegister {
+    const fn new(obis: ObisCode, name: &'static str, unit: Unit) -> Self {
+        DefaultRegister { obis, name, unit }
+    }
+
+    const fn octets(obis: &'static [u8], name: &'static str, unit: Unit) -> Self {
+        DefaultRegister::new(ObisCode::from_octet_str(obis), name, unit)
+    }
+}
+
+/// Registers captured without any configuration.
+///
+/// The names of the first six are the subtopics evcc expects (see
+/// `publish_data`).
+const DEFAULT_REGISTERS: &[DefaultRegister] = &[
+    DefaultRegister::new(OBIS_CURRENT_NET_POWER, "power", Unit::Watt),
+    DefaultRegister::new(OBIS_TOTAL_INBOUND_COUNT, "energy_import", Unit::WattHour),
+    DefaultRegister::new(OBIS_TOTAL_OUTBOUND_COUNT, "energy_export", Unit::WattHour),
+    DefaultRegister::new(OBIS_LINE_ONE, "l1", Unit::Watt),
+    DefaultRegister::new(OBIS_LINE_TWO, "l2", Unit::Watt),
+    DefaultRegister::new(OBIS_LINE_THREE, "l3", Unit::Watt),
+    DefaultRegister::octets(&[1, 0, 1, 8, 1, 255], "energy_import_t1", Unit::WattHour),
+    DefaultRegister::octets(&[1, 0, 1, 8, 2, 255], "energy_import_t2", Unit::WattHour),
+    DefaultRegister::octets(&[1, 0, 2, 8, 1, 255], "energy_export_t1", Unit::WattHour),
+    DefaultRegister::octets(&[1, 0, 2, 8, 2, 255], "energy_export_t2", Unit::WattHour),
+    DefaultRegister::octets(&[1, 0, 32, 7, 0, 255], "voltage_l1", Unit::Volt),
+    DefaultRegister::octets(&[1, 0, 52, 7, 0, 255], "voltage_l2", Unit::Volt),
+    DefaultRegister::octets(&[1, 0, 72, 7, 0, 255], "voltage_l3", Unit::Volt),
+    DefaultRegister::octets(&[1, 0, 31, 7, 0, 255], "current_l1", Unit::Ampere),
+    DefaultRegister::octets(&[1, 0, 51, 7, 0, 255], "current_l2", Unit::Ampere),
+    DefaultRegister::octets(&[1, 0, 71, 7, 0, 255], "current_l3", Unit::Ampere),
+    DefaultRegister::octets(&[1, 0, 14, 7, 0, 255], "frequency", Unit::Hertz),
+];
+
+/// Mapping of OBIS codes to the names of the registers.
+///
+/// Entries of the list response which aren't in the table are still captured,
+/// but only under their OBIS code.
+#[derive(Debug, Clone)]
+pub struct RegisterTable {
+    registers: Vec<Register>,
+}
+
+impl Default for RegisterTable {
+    fn default() -> Self {
+        let registers = DEFAULT_REGISTERS
+            .iter()
+            .map(|register| {
+                Register {
+                    obis: register.obis.clone(),
+                    name: register.name.to_string(),
+                    unit: Some(register.unit.clone()),
+                }
+            })
+            .collect();
+
+        RegisterTable { registers }
+    }
+}
+
+impl RegisterTable {
+    /// Creates the built-in table extended by `registers`.
+    ///
+    /// A configured register replaces the built-in one with the same OBIS
+    /// code.
+    pub fn new(registers: &[Register]) -> Result<Self, Error> {
+        let mut table = RegisterTable::default();
+
+        for register in registers {
+            table
+                .registers
+                .retain(|existing| existing.obis != register.obis);
+            table.registers.push(register.clone());
+        }
+        table.validate()?;
+
+        Ok(table)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        for (index, register) in self.registers.iter().enumerate() {
+            let valid_name = !register.name.is_empty()
+                && register
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if !valid_name {
+                bail!(
+                    "Register {} has an invalid name \"{}\" (allowed are letters, digits, '_' and \
+                     '-')",
+                    register.obis,
+                    register.name
+                );
+            }
+
+            let duplicate = self.registers[..index]
+                .iter()
+                .find(|other| other.name == register.name);
+            if let Some(other) = duplicate {
+                bail!(
+                    "Registers {} and {} have the same name \"{}\"",
+                    other.obis,
+                    register.obis,
+                    register.name
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn registers(&self) -> &[Register] { &self.registers }
+
+    pub fn by_name(&self, name: &str) -> Option<&Register> {
+        self.registers.iter().find(|register| register.name == name)
+    }
+
+    pub fn get(&self, obis: &ObisCode) -> Option<&Register> {
+        self.registers
+            .iter()
+            .find(|register| &register.obis == obis)
+    }
+}
@@ -0,0 +1,117 @@
+use std::time::Duration;
+
+use anyhow::{Context, Error};
+use clap::ValueEnum;
+use serde::Deserialize;
+use tokio::io::AsyncWriteExt;
+use tokio_serial::{DataBits, SerialPortType, SerialStream, StopBits};
+
+use crate::config::UsbDevice;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowControl {
+    None,
+    Software,
+    Hardware,
+}
+
+/// Settings of the serial line of a meter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineSettings {
+    pub baud_rate:    u32,
+    pub data_bits:    DataBits,
+    pub parity:       Parity,
+    pub stop_bits:    StopBits,
+    pub flow_control: FlowControl,
+    /// Time without a reading after which the port is reopened.
+    pub read_timeout: Option<Duration>,
+    /// Bytes written to the meter after opening the port.
+    pub wake_up:      Vec<u8>,
+}
+
+/// The settings of transparent IR heads of SML meters (e.g. Hichi, bitShake
+/// or volkszaehler.org): 9600 baud 8N1, reopened after 30 seconds without a
+/// reading.
+impl Default for LineSettings {
+    fn default() -> Self {
+        LineSettings {
+            baud_rate:    9600,
+            data_bits:    DataBits::Eight,
+            parity:       Parity::None,
+            stop_bits:    StopBits::One,
+            flow_control: FlowControl::None,
+            read_timeout: Some(Duration::from_secs(30)),
+            wake_up:      Vec::new(),
+        }
+    }
+}
+
+impl From<Parity> for tokio_serial::Parity {
+    fn from(parity: Parity) -> Self {
+        match parity {
+            Parity::None => tokio_serial::Parity::None,
+            Parity::Odd => tokio_serial::Parity::Odd,
+            Parity::Even => tokio_serial::Parity::Even,
+        }
+    }
+}
+
+impl From<FlowControl> for tokio_serial::FlowControl {
+    fn from(flow_control: FlowControl) -> Self {
+        match flow_control {
+            FlowControl::None => tokio_serial::FlowControl::None,
+            FlowControl::Software => tokio_serial::FlowControl::Software,
+            FlowControl::Hardware => tokio_serial::FlowControl::Hardware,
+        }
+    }
+}
+
+/// Opens the serial port at `port` and sends the wake-up sequence of the
+/// settings.
+pub async fn open_port(port: &str, settings: &LineSettings) -> Result<SerialStream, Error> {
+    let builder = tokio_serial::new(port, settings.baud_rate)
+        .data_bits(settings.data_bits)
+        .parity(settings.parity.into())
+        .stop_bits(settings.stop_bits)
+        .flow_control(settings.flow_control.into());
+    let mut serial = SerialStream::open(&builder)
+        .with_context(|| format!("Failed to open serial port {port}"))?;
+
+    if !settings.wake_up.is_empty() {
+        serial
+            .write_all(&settings.wake_up)
+            .await
+            .with_context(|| format!("Failed to send the wake-up sequence to {port}"))?;
+    }
+
+    Ok(serial)
+}
+
+/// Looks up the path of a USB serial port by its ids.
+pub fn find_usb_port(usb: &UsbDevice) -> Result<String, Error> {
+    let ports = tokio_serial::available_ports().context("Failed to list serial ports")?;
+
+    ports
+        .into_iter()
+        .find(|port| {
+            match &port.port_type {
+                SerialPortType::UsbPort(info) => {
+                    info.vid == usb.vid
+                        && info.pid == usb.pid
+                        && (usb.serial.is_none() || info.serial_number == usb.serial)
+                },
+                _ => false,
+            }
+        })
+        .map(|port| port.port_name)
+        .with_context(|| format!("No serial port of {usb} found"))
+}
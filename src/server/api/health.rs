@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use axum::{http::header, response::Response};
+
+use crate::server::Meter;
+
+/// Health of every meter: the age of its latest reading and the connection
+/// to the MQTT broker with the number of published and failed messages.
+///
+/// Responds with `503` if any meter has no fresh reading or isn't connected
+/// to the broker, so it can be used as health check of a container.
+pub async fn handler(meters: Arc<Vec<Meter>>) -> Response {
+    let mut healthy = true;
+    let meters: Vec<_> = meters
+        .iter()
+        .map(|meter| {
+            let age = meter.latest_reading.age();
+            let stale = age.is_none_or(|age| meter.latest_reading.is_stale(age));
+            let mqtt = meter.mqtt.health();
+            healthy &= !stale && mqtt.connected;
+
+            serde_json::json!({
+                "name": meter.name,
+                "reading_age": age.map(|age| age.as_secs()),
+                "stale": stale,
+                "mqtt": mqtt,
+            })
+        })
+        .collect();
+    let body = serde_json::json!({
+        "status": if healthy { "ok" } else { "degraded" },
+        "meters": meters,
+    });
+
+    Response::builder()
+        .status(if healthy { 200 } else { 503 })
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body.to_string().into())
+        .unwrap()
+}
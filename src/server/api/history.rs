@@ -0,0 +1,199 @@
+use std::{convert::Infallible, sync::Arc};
+
+use anyhow::{bail, Context, Error};
+use axum::{body::Body, extract::Query, http::header, response::Response};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Deserialize;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::error_response;
+use crate::database::{HistoryField, HistoryQuery, QueryError, ReadonlyDatabase, Resolution};
+
+/// Number of serialized buckets buffered before the database reader waits for
+/// the client.
+const CHUNK_QUEUE_SIZE: usize = 64;
+
+/// Maximum number of buckets of a request, e.g. 1m over four weeks or 15m
+/// over a year.
+const MAX_BUCKETS: i64 = 40_320;
+
+/// Query parameters of `GET /api/history`, all optional.
+#[derive(Deserialize)]
+pub struct HistoryParams {
+    /// Start of the range as RFC 3339 date or unix time, default `to` - 1 day
+    from:       Option<String>,
+    /// End of the range as RFC 3339 date or unix time, default now
+    to:         Option<String>,
+    /// `1m`, `15m`, `1h` or `1d`, default `1h`
+    resolution: Option<String>,
+    /// Comma separated list of fields, default all
+    fields:     Option<String>,
+    /// Name of the meter, default all
+    meter:      Option<String>,
+}
+
+impl HistoryParams {
+    fn parse(self) -> Result<HistoryQuery, Error> {
+        let to = match self.to {
+            Some(to) => parse_time(&to).context("Invalid parameter \"to\"")?,
+            None => Utc::now(),
+        };
+        let from = match self.from {
+            Some(from) => parse_time(&from).context("Invalid parameter \"from\"")?,
+            None => to - TimeDelta::days(1),
+        };
+        if from >= to {
+            bail!("Parameter \"from\" must be before \"to\"");
+        }
+
+        let resolution = match self.resolution {
+            Some(resolution) => resolution.parse()?,
+            None => Resolution::Hour,
+        };
+
+        let fields = match self.fields {
+            Some(fields) => {
+                fields
+                    .split(',')
+                    .map(|field| field.trim().parse())
+                    .collect::<Result<Vec<_>, _>>()?
+            },
+            None => HistoryField::ALL.to_vec(),
+        };
+
+        let query = HistoryQuery {
+            from,
+            to,
+            resolution,
+            fields,
+            meter: self.meter,
+        };
+        if query.buckets() > MAX_BUCKETS {
+            bail!(
+                "The range holds {} buckets of {}, at most {MAX_BUCKETS} are allowed",
+                query.buckets(),
+                resolution.name()
+            );
+        }
+
+        Ok(query)
+    }
+}
+
+fn parse_time(value: &str) -> Result<DateTime<Utc>, Error> {
+    if let Ok(timestamp) = value.parse::<i64>() {
+        return DateTime::from_timestamp(timestamp, 0).context("Unix time out of range");
+    }
+
+    Ok(DateTime::parse_from_rfc3339(value)?.to_utc())
+}
+
+/// Streams the downsampled readings as
+/// `{"from": .., "to": .., "resolution": .., "fields": [..], "meter": ..,
+/// "points": [..]}`
+/// while they are read from the database, so large ranges aren't buffered.
+pub async fn handler(database: Arc<ReadonlyDatabase>, params: Query<HistoryParams>) -> Response {
+    let query = match params.0.parse() {
+        Ok(query) => query,
+        Err(error) => return error_response(400, format!("{error:#}")),
+    };
+
+    let (started_tx, started_rx) = oneshot::channel();
+    let (chunk_tx, chunk_rx) = mpsc::channel::<Result<String, Infallible>>(CHUNK_QUEUE_SIZE);
+
+    // SQLite blocks, so the query runs on the blocking thread pool.
+    tokio::task::spawn_blocking(move || {
+        let fields: Vec<_> = query.fields.iter().map(|field| field.name()).collect();
+        let head = serde_json::json!({
+            "from": query.from.to_rfc3339(),
+            "to": query.to.to_rfc3339(),
+            "resolution": query.resolution.name(),
+            "fields": fields,
+            "meter": query.meter,
+        })
+        .to_string();
+        // Open the object and append the points array to it.
+        let mut chunk = format!("{},\"points\":[", head.trim_end_matches('}'));
+        let mut started = Some(started_tx);
+
+        let result = database.history(&query, |point| {
+            if let Some(started) = started.take() {
+                let _ = started.send(Ok(()));
+            } else {
+                chunk.push(',');
+            }
+            chunk += &point.to_string();
+
+            // `false` stops reading once the client went away.
+            chunk_tx
+                .blocking_send(Ok(std::mem::take(&mut chunk)))
+                .is_ok()
+        });
+
+        match (result, started) {
+            // Nothing was sent yet, so the error can still be reported with
+            // a proper status.
+            (Err(error), Some(started)) => {
+                let _ = started.send(Err(error));
+            },
+            (Err(error), None) => {
+                // The response is already streaming, truncate it.
+                log::warn!("History query failed while streaming: {error}");
+            },
+            (Ok(()), started) => {
+                if let Some(started) = started {
+                    let _ = started.send(Ok(()));
+                }
+                chunk += "]}";
+                let _ = chunk_tx.blocking_send(Ok(chunk));
+            },
+        }
+    });
+
+    match started_rx.await {
+        Ok(Ok(())) => {
+            Response::builder()
+                .status(200)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from_stream(ReceiverStream::new(chunk_rx)))
+                .unwrap()
+        },
+        Ok(Err(error @ QueryError::Timeout(_))) => error_response(504, error),
+        Ok(Err(error @ QueryError::Sqlite(_))) => error_response(500, error),
+        Err(error) => error_response(500, error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(from: &str, to: &str, resolution: &str) -> HistoryParams {
+        HistoryParams {
+            from:       Some(from.to_string()),
+            to:         Some(to.to_string()),
+            resolution: Some(resolution.to_string()),
+            fields:     None,
+            meter:      None,
+        }
+    }
+
+    #[test]
+    fn max_buckets() {
+        params("2024-03-01T00:00:00Z", "2024-03-29T00:00:00Z", "1m")
+            .parse()
+            .unwrap();
+        params("2024-01-01T00:00:00Z", "2025-01-01T00:00:00Z", "15m")
+            .parse()
+            .unwrap();
+
+        let error = params("2024-03-01T00:00:00Z", "2024-03-29T00:00:01Z", "1m")
+            .parse()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The range holds 40321 buckets of 1m, at most 40320 are allowed"
+        );
+    }
+}
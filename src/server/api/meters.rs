@@ -0,0 +1,26 @@
+use std::sync::Arc;
+
+use axum::{extract::Path, http::header, response::Response};
+
+use super::error_response;
+use crate::server::Meter;
+
+/// Lists the names of all meters as `{"meters": [..]}`.
+pub async fn list_handler(meters: Arc<Vec<Meter>>) -> Response {
+    let names: Vec<_> = meters.iter().map(|meter| &meter.name).collect();
+    let body = serde_json::json!({ "meters": names });
+
+    Response::builder()
+        .status(200)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body.to_string().into())
+        .unwrap()
+}
+
+/// The latest reading of the meter `name`, like `GET /api/now`.
+pub async fn now_handler(meters: Arc<Vec<Meter>>, Path(name): Path<String>) -> Response {
+    match meters.iter().find(|meter| meter.name == name) {
+        Some(meter) => super::now::handler(meter.latest_reading.clone()).await,
+        None => error_response(404, format!("Unknown meter \"{name}\"")),
+    }
+}
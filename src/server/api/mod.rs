@@ -1,2 +1,18 @@
+pub mod health;
+pub mod history;
+pub mod meters;
 pub mod now;
-pub mod query;
\ No newline at end of file
+pub mod query;
+
+use axum::{http::header, response::Response};
+
+/// Response with a JSON body of the form `{"error": "<message>"}`.
+fn error_response(status: u16, message: impl ToString) -> Response {
+    let json = serde_json::json!({ "error": message.to_string() });
+
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(json.to_string().into())
+        .unwrap()
+}
@@ -1,22 +1,15 @@
-use std::sync::Arc;
-use axum::http::header;
-use axum::response::Response;
-use crossbeam_utils::atomic::AtomicCell;
-use crate::meter_reading::MeterReading;
+use axum::{http::header, response::Response};
 
-pub async fn handler(latest_reading_cell: Arc<AtomicCell<Option<MeterReading>>>) -> Response {
-    let reading = latest_reading_cell.take();
+use crate::server::LatestReading;
 
-    let status = if reading.is_some() { 200 } else { 204 };
+pub async fn handler(latest_reading: LatestReading) -> Response {
+    latest_reading.respond_with(|received| {
+        let body = serde_json::to_string(&received.reading).unwrap();
 
-    let body = match reading {
-        Some(reading) => serde_json::to_string(&reading).unwrap(),
-        None => "".to_string()
-    };
-
-    Response::builder()
-        .status(status)
-        .header(header::CONTENT_TYPE, "application/json")
-        .body(body.into())
-        .unwrap()
-}
\ No newline at end of file
+        Response::builder()
+            .status(200)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body.into())
+            .unwrap()
+    })
+}
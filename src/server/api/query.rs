@@ -1,29 +1,57 @@
 use std::sync::Arc;
-use axum::http::header;
-use axum::response::Response;
-use serde::Serialize;
-use crate::database::ReadonlyDatabase;
-
 
+use axum::{http::header, response::Response};
 
+use super::error_response;
+use crate::database::{QueryError, ReadonlyDatabase};
 
 pub async fn handler(database: Arc<ReadonlyDatabase>, body: String) -> Response {
-    let result = database.query(&body);
+    // SQLite blocks, so the query runs on the blocking thread pool.
+    let result = tokio::task::spawn_blocking(move || database.query(&body)).await;
+
     match result {
-        Ok(query_result) => {
-            let json = serde_json::to_string(&query_result).unwrap();
-            
+        Ok(Ok(query_result)) => {
             Response::builder()
                 .status(200)
                 .header(header::CONTENT_TYPE, "application/json")
-                .body(json.into())
+                .body(serde_json::to_string(&query_result).unwrap().into())
                 .unwrap()
-        }
-        Err(error) => {
-            Response::builder()
-                .status(400)
-                .body(format!("{{\"error\": \"{}\"}}", error.to_string()).into())
-                .unwrap()
-        }
+        },
+        Ok(Err(error @ QueryError::Timeout(_))) => error_response(504, error),
+        Ok(Err(error @ QueryError::Sqlite(_))) => error_response(400, error),
+        Err(error) => error_response(500, error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{database::Database, test_util::TempPath};
+
+    #[tokio::test]
+    async fn status() {
+        let path = TempPath::new("query.db");
+        Database::open(path.path(), Some("grid")).unwrap();
+        let database =
+            Arc::new(ReadonlyDatabase::open(path.path(), Duration::from_millis(100), 10).unwrap());
+
+        let status = |query: &str| {
+            let database = database.clone();
+            let query = query.to_string();
+            async move { handler(database, query).await.status() }
+        };
+        assert_eq!(status("SELECT COUNT(*) FROM Readings").await, 200);
+        assert_eq!(status("DELETE FROM Readings").await, 400);
+        assert_eq!(status("SELECT 1; DELETE FROM Readings").await, 400);
+        assert_eq!(
+            status(
+                "WITH RECURSIVE Numbers(N) AS (SELECT 1 UNION ALL SELECT N + 1 FROM Numbers) \
+                 SELECT COUNT(*) FROM Numbers"
+            )
+            .await,
+            504
+        );
     }
-}
\ No newline at end of file
+}
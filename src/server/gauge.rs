@@ -1,18 +1,17 @@
-use std::sync::Arc;
-
 use axum::{http::header, response::Response};
-use crossbeam_utils::atomic::AtomicCell;
-
-use crate::meter_reading::MeterReading;
 
-pub async fn handler(latest_reading_cell: Arc<AtomicCell<Option<MeterReading>>>) -> Response {
-    let reading = latest_reading_cell.take();
-
-    let status = if reading.is_some() { 200 } else { 204 };
+/// Serves the page even without a current reading, only `/now` polled by
+/// the page reports a missing or stale reading.
+pub async fn handler() -> Response {
+    Response::builder()
+        .status(200)
+        .header(header::CONTENT_TYPE, "text/html")
+        .body(GAUGE_PAGE.into())
+        .unwrap()
+}
 
-    let body = match reading {
-        Some(reading) => 
-            r#"
+/// Page polling `/now` and showing the readings as gauges.
+const GAUGE_PAGE: &str = r#"
 <!DOCTYPE HTML PUBLIC "-//W3C//DTD HTML 4.01 Transitional//EN""http://www.w3.org/TR/html4/loose.dtd">
 <html>
 	<head>
@@ -286,7 +285,7 @@ pub async fn handler(latest_reading_cell: Arc<AtomicCell<Option<MeterReading>>>)
 				setInterval(function () {
 					$.ajax({
 						type: "GET",
-						url: "http://10.15.40.34:3000/now",
+						url: "/now",
  
 						success: function(data, status){
 							var response = data;
@@ -332,15 +331,4 @@ pub async fn handler(latest_reading_cell: Arc<AtomicCell<Option<MeterReading>>>)
 		</div>
 	</body>
 </html>
-"#.to_string(),
-
-        // format!("{reading}"),
-        None => "".to_string(),
-    };
-
-    Response::builder()
-        .status(status)
-        .header(header::CONTENT_TYPE, "text/html")
-        .body(body.into())
-        .unwrap()
-}
+"#;
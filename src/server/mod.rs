@@ -1,65 +1,160 @@
-// mod api;
+mod api;
 mod gauge;
 mod now;
 mod root;
 
-use std::{io, sync::Arc};
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
 
-use axum::{routing::get, Router};
-use crossbeam_utils::atomic::AtomicCell;
+use axum::{http::header,
+           response::Response,
+           routing::{get, post},
+           Router};
+use chrono::Utc;
+use tokio::sync::watch;
 
-// use crate::database::ReadonlyDatabase;
-use crate::meter_reading::MeterReading;
+use crate::{database::ReadonlyDatabase, meter_reading::ReceivedReading, mqtt::MqttState};
+
+/// A meter as seen by the server.
+pub struct Meter {
+    pub name:           String,
+    pub latest_reading: LatestReading,
+    pub mqtt:           Arc<MqttState>,
+}
+
+/// Shared view on the latest reading.
+///
+/// Reading it doesn't consume the value, so any number of clients see the
+/// same reading until the next telegram replaces it.
+#[derive(Clone)]
+pub struct LatestReading {
+    receiver:    watch::Receiver<Option<ReceivedReading>>,
+    stale_after: Duration,
+}
+
+impl LatestReading {
+    pub fn new(receiver: watch::Receiver<Option<ReceivedReading>>, stale_after: Duration) -> Self {
+        LatestReading {
+            receiver,
+            stale_after,
+        }
+    }
+
+    /// Age of the latest reading, `None` if no reading was received yet.
+    pub fn age(&self) -> Option<Duration> {
+        let latest = self.receiver.borrow();
+        let received = latest.as_ref()?;
+
+        Some(
+            (Utc::now() - received.received_at)
+                .to_std()
+                .unwrap_or_default(),
+        )
+    }
+
+    pub fn is_stale(&self, age: Duration) -> bool { age > self.stale_after }
+
+    /// Builds the response from the latest reading with `respond`.
+    ///
+    /// Responds with `204` instead if no reading was received yet, and with
+    /// `503` and the age of the last reading if it is older than the
+    /// staleness threshold.
+    pub fn respond_with(&self, respond: impl FnOnce(&ReceivedReading) -> Response) -> Response {
+        let latest = self.receiver.borrow();
+
+        let Some(received) = latest.as_ref() else {
+            return Response::builder().status(204).body("".into()).unwrap();
+        };
+
+        let age = (Utc::now() - received.received_at)
+            .to_std()
+            .unwrap_or_default();
+        if age > self.stale_after {
+            let body = serde_json::json!({
+                "error": "Latest reading is stale",
+                "age": age.as_secs(),
+                "received_at": received.received_at.to_rfc3339(),
+            });
+
+            return Response::builder()
+                .status(503)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::AGE, age.as_secs())
+                .body(body.to_string().into())
+                .unwrap();
+        }
+
+        respond(received)
+    }
+}
 
 pub struct Server {
-    app:  Router,
-    port: u16,
+    app:     Router,
+    address: SocketAddr,
 }
 
 impl Server {
-    pub fn create(port: u16, latest_reading_cell: Arc<AtomicCell<Option<MeterReading>>>) -> Self {
-        let latest_reading_cell = (latest_reading_cell.clone(), latest_reading_cell.clone());
+    /// Creates the server for the `meters`; `/now`, `/gauge` and `/api/now`
+    /// show the first one.
+    ///
+    /// `POST /api/query` and `GET /api/history` are only served if a
+    /// `readonly_database` is given.
+    pub fn create(
+        address: SocketAddr,
+        meters: Vec<Meter>,
+        readonly_database: Option<ReadonlyDatabase>,
+    ) -> Self {
+        let meters = Arc::new(meters);
+        let latest_reading = &meters[0].latest_reading;
+        let latest_reading = (latest_reading.clone(), latest_reading.clone());
 
-        // let readonly_database = Arc::new(ReadonlyDatabase::load().unwrap());
-
-        // build our application with a single route
-        let app = Router::new()
+        let mut app = Router::new()
             .route("/", get(root::get_handler))
+            .route("/now", get(move || now::handler(latest_reading.0.clone())))
+            .route("/gauge", get(gauge::handler))
             .route(
-                "/now",
-                get(move || now::handler(latest_reading_cell.0.clone())),
+                "/api/now",
+                get(move || api::now::handler(latest_reading.1.clone())),
             )
+            .route("/api/meters", {
+                let meters = meters.clone();
+                get(move || api::meters::list_handler(meters.clone()))
+            })
+            .route("/api/health", {
+                let meters = meters.clone();
+                get(move || api::health::handler(meters.clone()))
+            })
             .route(
-                "/gauge",
-                get(move || gauge::handler(latest_reading_cell.1.clone())),
+                "/api/meters/:name/now",
+                get(move |name| api::meters::now_handler(meters.clone(), name)),
             );
-        // .route(
-        //     "/api/now",
-        //     get(move || api::now::handler(latest_reading_cell.1.clone())),
-        // );
-        // .route("/api/query", post(move |body: String|
-        // api::query::handler(readonly_database.clone(), body)));
-
-        Server { app, port }
-    }
 
-    pub fn enter(self) -> io::Result<()> {
-        tokio::runtime::Builder::new_current_thread()
-            .enable_io()
-            .build()
-            .unwrap()
-            .block_on(async move {
-                // let addr = format!("127.0.0.1:{}", self.port);
-                let addr = format!("0.0.0.0:{}", self.port);
-                let listener = tokio::net::TcpListener::bind(addr).await?;
-
-                let future = axum::serve(listener, self.app);
-                println!(
-                    "Now listening for HTTP requests on TCP port {}...",
-                    self.port
+        if let Some(readonly_database) = readonly_database {
+            let readonly_database = Arc::new(readonly_database);
+            let readonly_database = (readonly_database.clone(), readonly_database);
+            app = app
+                .route(
+                    "/api/query",
+                    post(move |body: String| {
+                        api::query::handler(readonly_database.0.clone(), body)
+                    }),
+                )
+                .route(
+                    "/api/history",
+                    get(move |params| api::history::handler(readonly_database.1.clone(), params)),
                 );
+        }
 
-                future.await
-            })
+        Server { app, address }
+    }
+
+    /// Serves HTTP requests on the runtime of the caller until an I/O error
+    /// occurs.
+    pub async fn serve(self) -> io::Result<()> {
+        let listener = tokio::net::TcpListener::bind(self.address).await?;
+
+        let future = axum::serve(listener, self.app);
+        log::info!("Now listening for HTTP requests on {}...", self.address);
+
+        future.await
     }
 }
@@ -1,18 +1,21 @@
-use axum::http::header;
-use axum::response::Response;
+use axum::{http::header, response::Response};
 
 pub async fn get_handler() -> Response {
     let help_text = "
         Service is running.
 
-        GET /now - get the latest meter reading
+        GET /now - get the latest meter reading as JSON
+        GET /gauge - show the latest meter reading as gauges
         GET /api/now - get the latest meter reading as JSON
+        GET /api/meters - list the names of the meters
+        GET /api/meters/<name>/now - get the latest reading of a meter as JSON
         POST /api/query - query the database with readonly SQLite statements
+        GET /api/history - get downsampled readings of a time range
     ";
-    
+
     Response::builder()
         .status(200)
         .header(header::CONTENT_TYPE, "text/plain")
         .body(help_text.into())
         .unwrap()
-}
\ No newline at end of file
+}
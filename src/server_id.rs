@@ -0,0 +1,138 @@
+use std::fmt::Display;
+
+use serde::{Serialize, Serializer};
+
+/// Identification of a meter, the server id of its SML messages.
+///
+/// Most meters send a 10 byte id which encodes the meter number of
+/// DIN 43863-5: the media (`1` for electricity), the manufacturer's
+/// FLAG id, the fabrication block and an eight digit serial number, e.g.
+/// `0a 01 48 4c 59 03 00 1f 9f 17` is `1 HLY03 0207 2343`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerId(Vec<u8>);
+
+/// The parts of a meter number as defined by DIN 43863-5.
+struct MeterNumber<'a> {
+    media:             u8,
+    manufacturer:      &'a str,
+    fabrication_block: u8,
+    serial:            u32,
+}
+
+impl ServerId {
+    pub fn new(bytes: &[u8]) -> Self { ServerId(bytes.to_vec()) }
+
+    pub fn as_bytes(&self) -> &[u8] { &self.0 }
+
+    fn meter_number(&self) -> Option<MeterNumber<'_>> {
+        let [_, media, manufacturer @ .., fabrication_block, a, b, c, d] = self.0.as_slice() else {
+            return None;
+        };
+        if self.0.len() != 10 || !manufacturer.iter().all(u8::is_ascii_uppercase) {
+            return None;
+        }
+
+        let serial = u32::from_be_bytes([*a, *b, *c, *d]);
+        if *fabrication_block > 99 || serial > 99_999_999 {
+            return None;
+        }
+
+        Some(MeterNumber {
+            media: *media,
+            manufacturer: std::str::from_utf8(manufacturer).ok()?,
+            fabrication_block: *fabrication_block,
+            serial,
+        })
+    }
+
+    /// The FLAG id of the manufacturer, e.g. `HLY`, if the id follows
+    /// DIN 43863-5.
+    pub fn manufacturer(&self) -> Option<&str> {
+        self.meter_number().map(|number| number.manufacturer)
+    }
+
+    fn hex(&self) -> String { self.0.iter().map(|byte| format!("{byte:02x}")).collect() }
+
+    /// The id with its parts joined by `-` instead of spaces, so it can be
+    /// used in MQTT topics, e.g. `1-HLY03-0207-2343`.
+    ///
+    /// Ids which don't follow DIN 43863-5 are formatted as hex string.
+    pub fn slug(&self) -> String {
+        match self.meter_number() {
+            Some(number) => {
+                format!(
+                    "{:X}-{}{:02}-{:04}-{:04}",
+                    number.media,
+                    number.manufacturer,
+                    number.fabrication_block,
+                    number.serial / 10_000,
+                    number.serial % 10_000
+                )
+            },
+            None => self.hex(),
+        }
+    }
+}
+
+/// Formats the meter number as printed on the meter, e.g.
+/// `1 HLY03 0207 2343`, or the id as hex string if it doesn't follow
+/// DIN 43863-5.
+impl Display for ServerId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.meter_number() {
+            Some(_) => write!(f, "{}", self.slug().replace('-', " ")),
+            None => write!(f, "{}", self.hex()),
+        }
+    }
+}
+
+impl Serialize for ServerId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER_ID: [u8; 10] = [0x0a, 0x01, 0x48, 0x4c, 0x59, 0x03, 0x00, 0x1f, 0x9f, 0x17];
+
+    #[test]
+    fn meter_number() {
+        let id = ServerId::new(&SERVER_ID);
+        assert_eq!(id.to_string(), "1 HLY03 0207 2343");
+        assert_eq!(id.slug(), "1-HLY03-0207-2343");
+        assert_eq!(id.manufacturer(), Some("HLY"));
+        assert_eq!(serde_json::to_string(&id).unwrap(), "\"1 HLY03 0207 2343\"");
+    }
+
+    #[test]
+    fn hex_fallback() {
+        let mut bytes = SERVER_ID.to_vec();
+        bytes[3] = 0x31;
+        for bytes in [
+            // Manufacturer `H1Y`
+            bytes.as_slice(),
+            // Manufacturer `hly`
+            &[0x0a, 0x01, 0x68, 0x6c, 0x79, 0x03, 0x00, 0x1f, 0x9f, 0x17],
+            // Fabrication block 100
+            &[0x0a, 0x01, 0x48, 0x4c, 0x59, 0x64, 0x00, 0x1f, 0x9f, 0x17],
+            // Serial number 100000000
+            &[0x0a, 0x01, 0x48, 0x4c, 0x59, 0x03, 0x05, 0xf5, 0xe1, 0x00],
+            // Too short and too long
+            &SERVER_ID[..9],
+            &[SERVER_ID.as_slice(), &[0x00]].concat(),
+            &[
+                0x0a, 0x01, 0x48, 0x4c, 0x59, 0x4c, 0x03, 0x00, 0x1f, 0x9f, 0x17,
+            ],
+            &[],
+        ] {
+            let id = ServerId::new(bytes);
+            let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+            assert_eq!(id.to_string(), hex);
+            assert_eq!(id.slug(), hex);
+            assert_eq!(id.manufacturer(), None);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use anyhow::{bail, Context, Error};
+use sml_rs::parser::complete::{self, File};
+
+/// Escape sequence and version 1 marker at the start of an SML transport
+/// frame.
+pub const FRAME_START: &[u8] = &[0x1b, 0x1b, 0x1b, 0x1b, 0x01, 0x01, 0x01, 0x01];
+
+/// Type field of a list in a type-length field.
+const TY_LIST: u8 = 0b111;
+
+/// Maximum nesting depth of lists, deeper ones are rejected instead of
+/// overflowing the stack.
+const MAX_DEPTH: usize = 32;
+
+/// Parses the messages of a decoded SML file one by one.
+///
+/// `sml_rs` only knows the open, close and get list responses and rejects the
+/// whole file if it contains any other message (e.g. a
+/// `GetProcParameterResponse` or an `AttentionResponse`). Such messages are
+/// skipped and reported with their message type instead, as are messages
+/// which fail to parse for other reasons (e.g. a CRC mismatch).
+pub fn parse_messages(bytes: &[u8]) -> Result<(File<'_>, Vec<String>), Error> {
+    let mut messages = Vec::new();
+    let mut diagnostics = Vec::new();
+
+    let mut input = bytes;
+    while !input.is_empty() {
+        // Padding after the last message.
+        if input[0] == 0x00 {
+            input = &input[1..];
+            continue;
+        }
+
+        let length = element_length(input)?;
+        let (message, rest) = input.split_at(length);
+        input = rest;
+
+        match complete::parse(message) {
+            Ok(file) => messages.extend(file.messages),
+            Err(e) => {
+                let message_type = match message_tag(message) {
+                    Some(tag) => format!("{} (0x{tag:08x})", message_type_name(tag)),
+                    None => "unknown type".to_string(),
+                };
+                diagnostics.push(format!("Skipped message of {message_type}: {e:?}"));
+            },
+        }
+    }
+
+    Ok((File { messages }, diagnostics))
+}
+
+/// Name of an SML message type as defined in BSI TR-03109-1.
+fn message_type_name(tag: u32) -> &'static str {
+    match tag {
+        0x0100 => "OpenRequest",
+        0x0101 => "OpenResponse",
+        0x0200 => "CloseRequest",
+        0x0201 => "CloseResponse",
+        0x0300 => "GetProfilePackRequest",
+        0x0301 => "GetProfilePackResponse",
+        0x0400 => "GetProfileListRequest",
+        0x0401 => "GetProfileListResponse",
+        0x0500 => "GetProcParameterRequest",
+        0x0501 => "GetProcParameterResponse",
+        0x0600 => "SetProcParameterRequest",
+        0x0700 => "GetListRequest",
+        0x0701 => "GetListResponse",
+        0x0800 => "GetCosemRequest",
+        0x0801 => "GetCosemResponse",
+        0x0900 => "SetCosemRequest",
+        0x0901 => "SetCosemResponse",
+        0x0a00 => "ActionCosemRequest",
+        0x0a01 => "ActionCosemResponse",
+        0xff01 => "AttentionResponse",
+        _ => "unknown type",
+    }
+}
+
+/// Reads the type-length field at the start of `input`.
+///
+/// Returns the type, the length and the number of bytes of the field. The
+/// length of a list is its number of elements, the length of any other type
+/// its number of bytes including the type-length field.
+fn type_length_field(input: &[u8]) -> Result<(u8, usize, usize), Error> {
+    let Some(&first) = input.first() else {
+        bail!("Unexpected end of SML message");
+    };
+    let ty = (first >> 4) & 0b111;
+    let mut length = (first & 0b1111) as usize;
+    let mut size = 1;
+
+    let mut more = first & 0x80 != 0;
+    while more {
+        let Some(&next) = input.get(size) else {
+            bail!("Unexpected end of SML message");
+        };
+        length = length
+            .checked_mul(16)
+            .context("Length of SML element out of range")?
+            | (next & 0b1111) as usize;
+        more = next & 0x80 != 0;
+        size += 1;
+    }
+
+    Ok((ty, length, size))
+}
+
+/// Number of bytes of the element at the start of `input`.
+fn element_length(input: &[u8]) -> Result<usize, Error> { nested_element_length(input, 0) }
+
+fn nested_element_length(input: &[u8], depth: usize) -> Result<usize, Error> {
+    if depth > MAX_DEPTH {
+        bail!("SML lists nested deeper than {MAX_DEPTH} levels");
+    }
+    let (ty, length, size) = type_length_field(input)?;
+
+    let total = if ty == TY_LIST {
+        let mut total = size;
+        for _ in 0..length {
+            total += nested_element_length(&input[total.min(input.len())..], depth + 1)?;
+        }
+        total
+    } else if length == 0 {
+        // End of message marker.
+        1
+    } else {
+        length
+    };
+
+    if total > input.len() {
+        bail!("Unexpected end of SML message");
+    }
+    Ok(total)
+}
+
+/// Reads the message type of a message, the tag of its body.
+fn message_tag(message: &[u8]) -> Option<u32> {
+    // Skip the message list, transaction id, group number and abort on error.
+    let (_, _, size) = type_length_field(message).ok()?;
+    let mut offset = size;
+    for _ in 0..3 {
+        offset += element_length(message.get(offset..)?).ok()?;
+    }
+
+    // The body is a list of the tag and the content.
+    let (_, _, size) = type_length_field(message.get(offset..)?).ok()?;
+    offset += size;
+    let (_, length, size) = type_length_field(message.get(offset..)?).ok()?;
+    let tag = message.get(offset + size..offset + length)?;
+    if tag.len() > 4 {
+        return None;
+    }
+
+    Some(tag.iter().fold(0, |tag, byte| (tag << 8) | *byte as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn type_length_field_of_one_byte() {
+        assert_eq!(type_length_field(&[0x72]).unwrap(), (TY_LIST, 2, 1));
+        assert_eq!(type_length_field(&[0x63, 0x01, 0x01]).unwrap(), (6, 3, 1));
+    }
+
+    #[test]
+    fn type_length_field_of_several_bytes() {
+        assert_eq!(type_length_field(&[0x83, 0x02]).unwrap(), (0, 0x32, 2));
+        assert_eq!(
+            type_length_field(&[0xf1, 0x80, 0x03]).unwrap(),
+            (TY_LIST, 0x103, 3)
+        );
+    }
+
+    #[test]
+    fn type_length_field_without_continuation() {
+        assert!(type_length_field(&[]).is_err());
+        assert!(type_length_field(&[0x81]).is_err());
+    }
+
+    #[test]
+    fn type_length_field_out_of_range() {
+        let mut input = vec![0x8f; 20];
+        input.push(0x0f);
+        assert!(type_length_field(&input).is_err());
+    }
+
+    #[test]
+    fn element_length_of_values() {
+        assert_eq!(element_length(&[0x03, 0xab, 0xcd, 0xef]).unwrap(), 3);
+        assert_eq!(element_length(&[0x01]).unwrap(), 1);
+        // End of message marker.
+        assert_eq!(element_length(&[0x00]).unwrap(), 1);
+    }
+
+    #[test]
+    fn element_length_of_lists() {
+        assert_eq!(element_length(&[0x72, 0x62, 0x01, 0x01, 0xff]).unwrap(), 4);
+        assert_eq!(element_length(&[0x72, 0x71, 0x01, 0x02, 0xab, 0xff]).unwrap(), 5);
+    }
+
+    #[test]
+    fn element_length_of_truncated_elements() {
+        assert!(element_length(&[0x04, 0xab]).is_err());
+        assert!(element_length(&[0x73, 0x62, 0x01]).is_err());
+    }
+
+    #[test]
+    fn element_length_limits_nesting() {
+        let nested = |depth| {
+            let mut input = vec![0x71; depth];
+            input.push(0x01);
+            input
+        };
+
+        assert_eq!(element_length(&nested(MAX_DEPTH)).unwrap(), MAX_DEPTH + 1);
+        assert!(element_length(&nested(MAX_DEPTH + 1)).is_err());
+        assert!(element_length(&nested(10_000)).is_err());
+    }
+
+    /// Message with a body of `tag` and an empty content.
+    fn message(tag: &[u8]) -> Vec<u8> {
+        let mut message = vec![0x76, 0x03, 0x01, 0x02, 0x62, 0x00, 0x62, 0x00, 0x72];
+        message.push(0x61 + tag.len() as u8);
+        message.extend_from_slice(tag);
+        message.extend_from_slice(&[0x01, 0x63, 0x00, 0x00, 0x00]);
+        message
+    }
+
+    #[test]
+    fn message_tag_of_message() {
+        assert_eq!(message_tag(&message(&[0x07, 0x01])), Some(0x0701));
+        assert_eq!(
+            message_tag(&message(&[0x00, 0x00, 0xff, 0x01])),
+            Some(0xff01)
+        );
+    }
+
+    #[test]
+    fn message_tag_of_invalid_message() {
+        assert_eq!(message_tag(&message(&[0x01, 0x02, 0x03, 0x04, 0x05])), None);
+        assert_eq!(message_tag(&message(&[0x07, 0x01])[..8]), None);
+    }
+}
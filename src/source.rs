@@ -0,0 +1,347 @@
+use std::{fmt::Display, path::PathBuf, str::FromStr};
+
+use anyhow::{anyhow, bail, Context, Error};
+use clap::ValueEnum;
+use serde::{de, Deserialize, Deserializer};
+use tokio::{io::{AsyncRead, AsyncWriteExt},
+            net::TcpStream};
+
+use crate::{capture,
+            config::{SerialLineConfig, UsbDevice},
+            serial::{self, FlowControl, LineSettings, Parity}};
+
+/// Where the bytes of a meter are read from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// Serial port at a path, e.g. `/dev/ttyUSB0`.
+    Serial(String),
+    /// USB serial port, looked up on every (re)connect.
+    Usb(UsbDevice),
+    /// TCP connection to a network IR bridge, e.g. ser2net or an ESP.
+    Tcp {
+        host: String,
+        port: u16,
+    },
+    /// Replay of a capture file at `speed`.
+    File {
+        path:  PathBuf,
+        speed: f64,
+    },
+    Stdin,
+}
+
+/// A source given as URL, along with the serial settings of its query.
+///
+/// - `serial:///dev/ttyUSB0?baud=9600&parity=even` or just `/dev/ttyUSB0`
+/// - `usb://10c4:ea60?serial=0001`
+/// - `tcp://10.0.0.5:8888`
+/// - `file://capture.bin?speed=10`
+/// - `-` for stdin
+///
+/// The query accepts the settings of [`SerialLineConfig`], `baud` as short
+/// form of `baud_rate`.
+#[derive(Debug, Clone)]
+pub struct SourceUrl {
+    pub source:   Source,
+    pub settings: SerialLineConfig,
+    /// Speed of a `file://` URL if its query sets one, which then overrides
+    /// the replay speed of the meter.
+    pub speed:    Option<f64>,
+}
+
+/// Reader of the bytes of a meter.
+pub type SourceReader = Box<dyn AsyncRead + Unpin + Send>;
+
+impl Source {
+    /// Whether the source is opened again after it ended. Stdin can't be,
+    /// and a replay would repeat its readings.
+    pub fn can_reopen(&self) -> bool {
+        matches!(
+            self,
+            Source::Serial(_) | Source::Usb(_) | Source::Tcp { .. }
+        )
+    }
+
+    /// Whether only a single meter can read the source.
+    pub fn is_exclusive(&self) -> bool { !matches!(self, Source::File { .. }) }
+}
+
+/// Opens `source` and returns what was opened, e.g. the path of a USB port,
+/// along with the reader.
+///
+/// The wake-up sequence of the settings is sent to serial ports and TCP
+/// bridges right away.
+pub async fn open(
+    source: &Source,
+    settings: &LineSettings,
+) -> Result<(String, SourceReader), Error> {
+    let reader: SourceReader = match source {
+        Source::Serial(path) => Box::new(serial::open_port(path, settings).await?),
+        Source::Usb(usb) => {
+            let path = serial::find_usb_port(usb)?;
+            let port = serial::open_port(&path, settings).await?;
+            return Ok((path, Box::new(port)));
+        },
+        Source::Tcp { host, port } => {
+            let mut stream = TcpStream::connect((host.as_str(), *port))
+                .await
+                .with_context(|| format!("Failed to connect to {source}"))?;
+            if !settings.wake_up.is_empty() {
+                stream
+                    .write_all(&settings.wake_up)
+                    .await
+                    .with_context(|| format!("Failed to send the wake-up sequence to {source}"))?;
+            }
+            Box::new(stream)
+        },
+        Source::File { path, speed } => Box::new(capture::replay(path, *speed)?),
+        Source::Stdin => Box::new(tokio::io::stdin()),
+    };
+
+    Ok((source.to_string(), reader))
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Serial(path) => write!(f, "{path}"),
+            Source::Usb(usb) => write!(f, "{usb}"),
+            Source::Tcp { host, port } => write!(f, "tcp://{host}:{port}"),
+            Source::File { path, .. } => write!(f, "replay of {}", path.display()),
+            Source::Stdin => write!(f, "stdin"),
+        }
+    }
+}
+
+impl FromStr for SourceUrl {
+    type Err = Error;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        if url == "-" {
+            return Ok(SourceUrl {
+                source:   Source::Stdin,
+                settings: SerialLineConfig::default(),
+                speed:    None,
+            });
+        }
+
+        let (scheme, rest) = url.split_once("://").unwrap_or(("serial", url));
+        let (location, query) = rest.split_once('?').unwrap_or((rest, ""));
+        if location.is_empty() {
+            bail!("Source \"{url}\" has no path or address");
+        }
+
+        let mut settings = SerialLineConfig::default();
+        let mut usb_serial = None;
+        let mut speed: Option<f64> = None;
+        for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+            let (name, value) = parameter
+                .split_once('=')
+                .with_context(|| format!("Parameter \"{parameter}\" of \"{url}\" has no value"))?;
+            match (scheme, name) {
+                ("usb", "serial") => usb_serial = Some(value.to_string()),
+                ("file", "speed") => speed = Some(parse_number(name, value)?),
+                _ => {
+                    // Clap only prints the outermost error, so the cause is
+                    // part of the message.
+                    set_setting(&mut settings, name, value).map_err(|e| {
+                        anyhow!("Invalid parameter \"{parameter}\" of \"{url}\": {e}")
+                    })?;
+                },
+            }
+        }
+
+        let source = match scheme {
+            "serial" => Source::Serial(location.to_string()),
+            "usb" => {
+                let (vid, pid) = location
+                    .split_once(':')
+                    .with_context(|| format!("Expected usb://<vid>:<pid> (got \"{url}\")"))?;
+                Source::Usb(UsbDevice {
+                    vid:    u16::from_str_radix(vid, 16)
+                        .with_context(|| format!("Invalid vendor id \"{vid}\""))?,
+                    pid:    u16::from_str_radix(pid, 16)
+                        .with_context(|| format!("Invalid product id \"{pid}\""))?,
+                    serial: usb_serial,
+                })
+            },
+            "tcp" => {
+                let (host, port) = location
+                    .rsplit_once(':')
+                    .with_context(|| format!("Expected tcp://<host>:<port> (got \"{url}\")"))?;
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                if host.is_empty() {
+                    bail!("Source \"{url}\" has no host");
+                }
+                Source::Tcp {
+                    host: host.to_string(),
+                    port: port
+                        .parse()
+                        .with_context(|| format!("Invalid port \"{port}\""))?,
+                }
+            },
+            "file" => {
+                if let Some(speed) = speed {
+                    if !speed.is_finite() || speed < 0.0 {
+                        bail!("speed must be 0 or positive (got {speed})");
+                    }
+                }
+                Source::File {
+                    path:  PathBuf::from(location),
+                    speed: speed.unwrap_or(1.0),
+                }
+            },
+            _ => {
+                bail!("Unknown scheme \"{scheme}\" of \"{url}\", expected serial, usb, tcp or file")
+            },
+        };
+
+        Ok(SourceUrl {
+            source,
+            settings,
+            speed,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for SourceUrl {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let url = String::deserialize(deserializer)?;
+        url.parse().map_err(|e| de::Error::custom(format!("{e:#}")))
+    }
+}
+
+/// Sets the serial setting `name` from a URL query.
+fn set_setting(settings: &mut SerialLineConfig, name: &str, value: &str) -> Result<(), Error> {
+    match name {
+        "baud" | "baud_rate" => settings.baud_rate = Some(parse_number(name, value)?),
+        "data_bits" => settings.data_bits = Some(parse_number(name, value)?),
+        "parity" => settings.parity = Some(parse_enum::<Parity>(value)?),
+        "stop_bits" => settings.stop_bits = Some(parse_number(name, value)?),
+        "flow_control" => settings.flow_control = Some(parse_enum::<FlowControl>(value)?),
+        "read_timeout" => settings.read_timeout = Some(parse_number(name, value)?),
+        "wake_up" => settings.wake_up = Some(value.to_string()),
+        _ => bail!("unknown setting"),
+    }
+
+    Ok(())
+}
+
+fn parse_number<T: FromStr>(name: &str, value: &str) -> Result<T, Error> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("{name} must be a number (got \"{value}\")"))
+}
+
+fn parse_enum<T: ValueEnum>(value: &str) -> Result<T, Error> {
+    T::from_str(value, true).map_err(|e| anyhow!(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(url: &str) -> Source { url.parse::<SourceUrl>().unwrap().source }
+
+    #[test]
+    fn serial_url() {
+        assert_eq!(
+            source("/dev/ttyUSB0"),
+            Source::Serial("/dev/ttyUSB0".to_string())
+        );
+
+        let url: SourceUrl = "serial:///dev/ttyUSB0?baud=300&parity=even&data_bits=7"
+            .parse()
+            .unwrap();
+        assert_eq!(url.source, Source::Serial("/dev/ttyUSB0".to_string()));
+        assert_eq!(url.settings.baud_rate, Some(300));
+        assert_eq!(url.settings.parity, Some(Parity::Even));
+        assert_eq!(url.settings.data_bits, Some(7));
+    }
+
+    #[test]
+    fn usb_url() {
+        assert_eq!(
+            source("usb://10c4:ea60?serial=0001"),
+            Source::Usb(UsbDevice {
+                vid:    0x10c4,
+                pid:    0xea60,
+                serial: Some("0001".to_string()),
+            })
+        );
+        assert_eq!(
+            source("usb://10C4:EA60"),
+            Source::Usb(UsbDevice {
+                vid:    0x10c4,
+                pid:    0xea60,
+                serial: None,
+            })
+        );
+    }
+
+    #[test]
+    fn tcp_url() {
+        assert_eq!(source("tcp://10.0.0.5:8888"), Source::Tcp {
+            host: "10.0.0.5".to_string(),
+            port: 8888,
+        });
+        assert_eq!(source("tcp://[::1]:8888"), Source::Tcp {
+            host: "::1".to_string(),
+            port: 8888,
+        });
+    }
+
+    #[test]
+    fn file_url() {
+        let url: SourceUrl = "file://capture.bin?speed=10".parse().unwrap();
+        assert_eq!(url.source, Source::File {
+            path:  PathBuf::from("capture.bin"),
+            speed: 10.0,
+        });
+        assert_eq!(url.speed, Some(10.0));
+
+        let url: SourceUrl = "file:///tmp/capture.bin".parse().unwrap();
+        assert_eq!(url.source, Source::File {
+            path:  PathBuf::from("/tmp/capture.bin"),
+            speed: 1.0,
+        });
+        assert_eq!(url.speed, None);
+    }
+
+    #[test]
+    fn stdin_url() {
+        assert_eq!(source("-"), Source::Stdin);
+    }
+
+    #[test]
+    fn invalid_urls() {
+        for url in [
+            "",
+            "tcp://",
+            "tcp://10.0.0.5",
+            "tcp://:8888",
+            "tcp://10.0.0.5:http",
+            "usb://10c4",
+            "usb://xyz:ea60",
+            "ftp://host/file",
+        ] {
+            assert!(url.parse::<SourceUrl>().is_err(), "{url}");
+        }
+    }
+
+    #[test]
+    fn invalid_query_values() {
+        for url in [
+            "/dev/ttyUSB0?baud=fast",
+            "/dev/ttyUSB0?parity=mark",
+            "/dev/ttyUSB0?flow_control=maybe",
+            "/dev/ttyUSB0?color=blue",
+            "/dev/ttyUSB0?baud",
+            "file://capture.bin?speed=-1",
+            "file://capture.bin?speed=inf",
+            "tcp://10.0.0.5:8888?serial=0001",
+        ] {
+            assert!(url.parse::<SourceUrl>().is_err(), "{url}");
+        }
+    }
+}
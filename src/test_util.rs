@@ -0,0 +1,29 @@
+use std::{fs,
+          path::{Path, PathBuf},
+          process,
+          sync::atomic::{AtomicUsize, Ordering}};
+
+/// A file in the temporary directory, removed on drop together with the
+/// `-wal` and `-shm` files of a SQLite database.
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    pub fn new(name: &str) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        let file = format!("power-meter-{}-{count}-{name}", process::id());
+        TempPath(std::env::temp_dir().join(file))
+    }
+
+    pub fn path(&self) -> &Path { &self.0 }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut path = self.0.clone().into_os_string();
+            path.push(suffix);
+            let _ = fs::remove_file(path);
+        }
+    }
+}
@@ -1,18 +1,19 @@
 use std::fmt::Display;
-use serde::Serialize;
+
+use serde::{Deserialize, Serialize};
 
 /// Units as defined in [DLMS/COSEM][dlms] or [IEC 62056][iec]
 ///
 /// This type only implements the units relevant for (and used by) power meters.
 ///
-/// Specification of the units taken from this [pdf][dlmspdf] ([archive.org][dlmsarchive]).
-/// See table on page 47.
+/// Specification of the units taken from this [pdf][dlmspdf]
+/// ([archive.org][dlmsarchive]). See table on page 47.
 ///
 /// [dlms]: https://www.dlms.com/dlms-cosem
 /// [iec]: https://en.wikipedia.org/wiki/IEC_62056
 /// [dlmspdf]: https://www.dlms.com/files/Blue-Book-Ed-122-Excerpt.pdf
 /// [dlmsarchive]: https://web.archive.org/web/20211130052659/https://www.dlms.com/files/Blue-Book-Ed-122-Excerpt.pdf
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 #[repr(u8)]
 #[non_exhaustive]
 pub enum Unit {
@@ -23,12 +24,16 @@ pub enum Unit {
     #[serde(alias = "Wh")]
     WattHour,
     /// voltage `[V]`
+    #[serde(alias = "V")]
     Volt,
     /// current `[A]`
+    #[serde(alias = "A")]
     Ampere,
     /// (phase) angle `[°]`
+    #[serde(alias = "°")]
     Degree,
     /// frequency `[Hz]`
+    #[serde(alias = "Hz")]
     Hertz,
 }
 
@@ -47,7 +52,8 @@ impl Unit {
 
     /// Creates a `Unit` instance from a DLMN/COSEM unit number.
     ///
-    /// Returns `None` if the given unit number doesn't match one of the supported units.
+    /// Returns `None` if the given unit number doesn't match one of the
+    /// supported units.
     pub fn from_u8(value: u8) -> Option<Self> {
         match value {
             8 => Some(Unit::Degree),
@@ -65,4 +71,4 @@ impl Display for Unit {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{}", self.as_str())
     }
-}
\ No newline at end of file
+}
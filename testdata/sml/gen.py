@@ -0,0 +1,125 @@
+#!/usr/bin/env python3
+# Generates the SML files the parser is tested with. None of the sample dumps
+# of real meters has more than one list response per file, so the files are
+# built here from the messages of the example meter `1 HLY03 0207 2343`.
+#
+#   multi_message.bin: open, attention, two list responses and close
+#   other_server.bin:  a second list response of another server id
+
+import os
+
+SERVER_ID = bytes.fromhex("0a01484c5903001f9f17")
+OTHER_SERVER_ID = bytes.fromhex("0a01454d4800000c8b5f")
+
+
+def crc16(data):
+    crc = 0xFFFF
+    for byte in data:
+        crc ^= byte
+        for _ in range(8):
+            crc = (crc >> 1) ^ 0x8408 if crc & 1 else crc >> 1
+    crc ^= 0xFFFF
+    return bytes([crc & 0xFF, crc >> 8])
+
+
+def octets(value):
+    assert len(value) < 15
+    return bytes([len(value) + 1]) + value
+
+
+def unsigned(value, size):
+    return bytes([0x61 + size]) + value.to_bytes(size, "big")
+
+
+def signed(value, size):
+    return bytes([0x51 + size]) + value.to_bytes(size, "big", signed=True)
+
+
+def sml_list(*elements):
+    return bytes([0x70 + len(elements)]) + b"".join(elements)
+
+
+NONE = b"\x01"
+
+
+def message(transaction, tag, body):
+    # A list of six elements, of which the CRC over the ones before and the
+    # end of message follow.
+    content = (
+        b"\x76"
+        + octets(bytes([0x00, 0x00, 0x00, transaction]))
+        + unsigned(0, 1)
+        + unsigned(0, 1)
+        + sml_list(unsigned(tag, 2), body)
+    )
+    return content + b"\x63" + crc16(content) + b"\x00"
+
+
+def open_response():
+    return sml_list(NONE, NONE, octets(b"\x00\x12\x34\x56"), octets(SERVER_ID), NONE, NONE)
+
+
+def attention_response():
+    # 81 81 c7 c7 fd 00: the request was processed
+    return sml_list(octets(SERVER_ID), octets(bytes.fromhex("8181c7c7fd00")), NONE, NONE)
+
+
+def entry(obis, value, unit=None, scaler=None):
+    return sml_list(
+        octets(bytes.fromhex(obis)),
+        NONE,
+        NONE,
+        NONE if unit is None else unsigned(unit, 1),
+        NONE if scaler is None else signed(scaler, 1),
+        value,
+        NONE,
+    )
+
+
+def list_response(server_id, sensor_time, *entries):
+    return sml_list(
+        NONE,
+        octets(server_id),
+        octets(bytes.fromhex("0100620affff")),
+        sml_list(unsigned(1, 1), unsigned(sensor_time, 4)),
+        sml_list(*entries),
+        NONE,
+        NONE,
+    )
+
+
+def close_response():
+    return sml_list(NONE)
+
+
+def frame(*messages):
+    data = b"\x1b\x1b\x1b\x1b\x01\x01\x01\x01" + b"".join(messages)
+    padding = -len(data) % 4
+    data += b"\x00" * padding + b"\x1b\x1b\x1b\x1b\x1a" + bytes([padding])
+    return data + crc16(data)
+
+
+ENERGY = entry("0100010800ff", signed(6074471, 4), unit=30, scaler=-1)
+POWER = entry("0100100700ff", signed(4215, 4), unit=27, scaler=-1)
+MANUFACTURER = entry("8181c78203ff", octets(b"HLY"))
+
+files = {
+    "multi_message.bin": frame(
+        message(1, 0x0101, open_response()),
+        message(2, 0xFF01, attention_response()),
+        message(3, 0x0701, list_response(SERVER_ID, 1234567, MANUFACTURER, ENERGY)),
+        message(4, 0x0701, list_response(SERVER_ID, 1234568, POWER)),
+        message(5, 0x0201, close_response()),
+    ),
+    "other_server.bin": frame(
+        message(1, 0x0101, open_response()),
+        message(2, 0x0701, list_response(SERVER_ID, 1234567, MANUFACTURER, ENERGY)),
+        message(3, 0x0701, list_response(OTHER_SERVER_ID, 1234568, POWER)),
+        message(4, 0x0201, close_response()),
+    ),
+}
+
+directory = os.path.dirname(os.path.abspath(__file__))
+for name, data in files.items():
+    with open(os.path.join(directory, name), "wb") as file:
+        file.write(data)
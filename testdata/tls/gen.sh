@@ -0,0 +1,29 @@
+#!/bin/sh
+# Generates the throwaway PKI of the TLS tests of the MQTT connection.
+set -e
+cd "$(dirname "$0")"
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+ca() {
+    openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes -days 36500 \
+        -keyout "$tmp/$1.key" -out "$1.pem" -subj "/CN=power-meter test $1" \
+        -addext basicConstraints=critical,CA:TRUE -addext keyUsage=critical,keyCertSign
+}
+
+# cert <name> <subject> <extensions>
+cert() {
+    openssl req -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes \
+        -keyout "$tmp/$1.key" -out "$tmp/$1.csr" -subj "/CN=$2"
+    openssl pkcs8 -topk8 -nocrypt -in "$tmp/$1.key" -out "$1.key"
+    printf '%s\n' "$3" > "$tmp/$1.ext"
+    openssl x509 -req -in "$tmp/$1.csr" -CA ca.pem -CAkey "$tmp/ca.key" -CAcreateserial \
+        -CAserial "$tmp/ca.srl" -days 36500 -out "$1.pem" -extfile "$tmp/$1.ext"
+}
+
+ca ca
+ca other-ca
+cert server broker.test "subjectAltName=DNS:broker.test,DNS:localhost
+extendedKeyUsage=serverAuth"
+cert client meter "extendedKeyUsage=clientAuth"
+printf 'secret\n' > password